@@ -0,0 +1,118 @@
+use crate::maven::sha256;
+use crate::spawn::{ORANGE, RESET};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Overrides where the shared artifact cache lives.
+pub const CACHE_ENV: &str = "LVJB_CACHE";
+
+/// The user-level cache shared by all projects: `$LVJB_CACHE`, or `lvjb`
+/// under `$XDG_CACHE_HOME` or `~/.cache`. None without a home directory.
+pub fn  cache_root() -> Option<PathBuf>
+{
+    if let Some(dir) = env::var_os(CACHE_ENV).filter(|d| !d.is_empty())
+    {
+        return Some(PathBuf::from(dir));
+    }
+    match env::var_os("XDG_CACHE_HOME").filter(|d| !d.is_empty())
+    {
+        Some(dir) => Some(PathBuf::from(dir).join("lvjb")),
+        None => env::var_os("HOME").filter(|d| !d.is_empty()).map(|h| PathBuf::from(h).join(".cache").join("lvjb")),
+    }
+}
+
+/// Where the artifact with `hash` (`sha256:<hex>`) is kept:
+/// `artifacts/<first two hex digits>/<rest>`.
+fn  blob_path(hash: &str) -> Option<PathBuf>
+{
+    let hex = hash.strip_prefix("sha256:").filter(|h| h.len() > 2 && h.chars().all(|c| c.is_ascii_hexdigit()))?;
+    Some(cache_root()?.join("artifacts").join(&hex[..2]).join(&hex[2..]))
+}
+
+/// Where the hash of what `url` served is kept.
+#[inline(always)]
+fn  index_path(url: &str) -> Option<PathBuf>
+{
+    let hash = sha256(url.as_bytes());
+    Some(cache_root()?.join("urls").join(hash.strip_prefix("sha256:")?))
+}
+
+/// Indexed for a URL the repository doesn't have, so `--offline` knows.
+pub const MISSING: &str = "missing";
+
+/// Hash of what `url` served when it was last downloaded and verified, or
+/// `MISSING`.
+pub fn  indexed(url: &str) -> Option<String>
+{
+    Some(fs::read_to_string(index_path(url)?).ok()?.trim().to_string())
+}
+
+/// The artifact with `hash`, if the cache has it intact. A damaged one is
+/// dropped.
+pub fn  read(hash: &str) -> Option<Vec<u8>>
+{
+    let path = blob_path(hash)?;
+    let bytes = fs::read(&path).ok()?;
+    if sha256(&bytes) != hash
+    {
+        eprintln!("{ORANGE}[CACHE]{RESET} {} is damaged, dropping it", path.display());
+        let _ = fs::remove_file(&path);
+        return None;
+    }
+    Some(bytes)
+}
+
+/// Writes `bytes` next to `dest` first, so an interrupted write never leaves
+/// a truncated file behind.
+pub fn  write_atomic(dest: &Path, bytes: &[u8]) -> Result<(), Box<dyn std::error::Error>>
+{
+    let name = dest.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let part = dest.with_file_name(format!(".{name}.{}.part", std::process::id()));
+    fs::write(&part, bytes)?;
+    fs::rename(&part, dest)?;
+    Ok(())
+}
+
+/// Adds verified `bytes` with `hash`, as served by `url`, to the cache.
+/// Blobs are read-only: projects hardlink them.
+pub fn  store(url: &str, bytes: &[u8], hash: &str) -> Result<(), Box<dyn std::error::Error>>
+{
+    let (Some(blob), Some(index)) = (blob_path(hash), index_path(url)) else { return Ok(()) };
+    if !blob.exists()
+    {
+        if let Some(dir) = blob.parent()
+        {
+            fs::create_dir_all(dir)?;
+        }
+        write_atomic(&blob, bytes)?;
+        let mut permissions = fs::metadata(&blob)?.permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&blob, permissions)?;
+    }
+    if let Some(dir) = index.parent()
+    {
+        fs::create_dir_all(dir)?;
+    }
+    write_atomic(&index, hash.as_bytes())
+}
+
+/// Notes that the repository doesn't have `url`.
+pub fn  mark_missing(url: &str) -> Result<(), Box<dyn std::error::Error>>
+{
+    let Some(index) = index_path(url) else { return Ok(()) };
+    if let Some(dir) = index.parent()
+    {
+        fs::create_dir_all(dir)?;
+    }
+    write_atomic(&index, MISSING.as_bytes())
+}
+
+/// Puts the cached artifact with `hash` at `dest`: a hardlink, or a copy
+/// across file systems. False if the cache doesn't have it.
+pub fn  link(hash: &str, dest: &Path) -> bool
+{
+    let Some(blob) = blob_path(hash).filter(|b| b.exists()) else { return false };
+    let _ = fs::remove_file(dest);
+    fs::hard_link(&blob, dest).is_ok() || fs::copy(&blob, dest).is_ok()
+}
@@ -1,29 +1,30 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use crate::maven::Resolved;
 use serde::{Serialize, Deserialize};
 use std::fs;
 
 pub const CACHE_FILE: &str = "lvjb.lock";
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 #[serde(default)]
 pub struct Cache
 {
     pub files:        HashMap<String, String>,
+    pub deps:         HashMap<String, Vec<String>>,
+    pub outputs:      HashMap<String, HashMap<String, String>>,
+    /// Wall time of each test class in milliseconds, from the last run.
+    pub test_durations: HashMap<String, u64>,
+    /// Sources each test class transitively touches, with the hashes they had
+    /// when it last passed.
+    pub test_sources: HashMap<String, HashMap<String, String>>,
     pub releases:     Vec<Option<(String, String)>>,
     pub url_libs:     Vec<String>,
-}
-
-impl Default for Cache
-{
-    fn default() -> Self
-    {
-        Cache
-        {
-            files: HashMap::new(),
-            releases: Vec::new(),
-            url_libs: Vec::new(),
-        }
-    }
+    /// Jars `lvjb fetch` put in `paths.lib`, with the dependency each is.
+    pub fetched:      BTreeMap<String, String>,
+    /// `sha256` of every downloaded jar in `paths.lib`, as verified.
+    pub libs:         BTreeMap<String, String>,
+    /// Dependency tree resolved from `[dependencies]`, by `group:artifact`.
+    pub tree:         BTreeMap<String, Resolved>,
 }
 
 impl Cache
@@ -36,6 +37,20 @@ impl Cache
         Ok(cache)
     }
     #[inline(always)]
+    pub fn load_or_init() -> Self
+    {
+        match Self::load()
+        {
+            Ok(x) => x,
+            Err(_) =>
+            {
+                let t = Cache::default();
+                let _ = t.write();
+                t
+            },
+        }
+    }
+    #[inline(always)]
     pub fn write(&self) -> Result<(), Box<dyn std::error::Error>>
     {
         fs::write(CACHE_FILE, toml::to_string(&self)?)?;
@@ -0,0 +1,123 @@
+use crate::config::*;
+use crate::jvm::*;
+use jni::objects::*;
+use jni::{JNIEnv, JavaVM};
+use std::sync::Mutex;
+
+/// `System.out`/`System.err` replacement written next to the annotations.
+/// While a thread (or a thread it started) is capturing, what it prints goes
+/// to its own buffer instead of the terminal.
+pub const CAPTURE: &str = "package lvjb;
+
+import java.io.*;
+
+public final class Capture extends OutputStream {
+    private static final InheritableThreadLocal<ByteArrayOutputStream> BUFFER = new InheritableThreadLocal<>();
+    private static PrintStream out, err;
+
+    private final PrintStream fallback;
+
+    private Capture(PrintStream fallback) { this.fallback = fallback; }
+
+    public static synchronized void install() {
+        if (out != null) return;
+        out = System.out;
+        err = System.err;
+        System.setOut(new PrintStream(new Capture(out), true));
+        System.setErr(new PrintStream(new Capture(err), true));
+    }
+
+    public static synchronized void uninstall() {
+        if (out == null) return;
+        System.setOut(out);
+        System.setErr(err);
+        out = err = null;
+    }
+
+    public static void begin() { BUFFER.set(new ByteArrayOutputStream()); }
+
+    public static String end() {
+        ByteArrayOutputStream buffer = BUFFER.get();
+        BUFFER.remove();
+        return buffer == null ? \"\" : buffer.toString();
+    }
+
+    private OutputStream target() {
+        ByteArrayOutputStream buffer = BUFFER.get();
+        return buffer != null ? buffer : fallback;
+    }
+
+    @Override public void write(int b) throws IOException { target().write(b); }
+    @Override public void write(byte[] b, int off, int len) throws IOException { target().write(b, off, len); }
+    @Override public void flush() throws IOException { target().flush(); }
+}
+";
+
+/// The `lvjb.Capture` class in use. Every test class loader has its own copy
+/// of it, so begin/end must go through the one that was installed.
+static CAPTURE_CLASS: Mutex<Option<GlobalRef>> = Mutex::new(None);
+
+#[inline(always)]
+fn  capture_class() -> Option<GlobalRef>
+{
+    CAPTURE_CLASS.lock().ok()?.clone()
+}
+
+/// Puts `lvjb.Capture` in front of `System.out` and `System.err`.
+pub fn  start_capture(jvm: &JavaVM, config: &Config) -> Result<(), Box<dyn std::error::Error>>
+{
+    let mut env = jvm.attach_current_thread()?;
+    let class = env.with_local_frame(16, |env| -> Result<GlobalRef, jni::errors::Error>
+    {
+        let loader = class_loader(env, config)?;
+        let class = load_class(env, &loader, "lvjb.Capture")?;
+        env.call_static_method(&class, "install", "()V", &[])?;
+        env.new_global_ref(class)
+    });
+    let class = match class
+    {
+        Ok(x) => x,
+        Err(e) =>
+        {
+            let msg = describe_exception(&mut env).unwrap_or_else(|_| e.to_string());
+            return Err(format!("Couldn't capture test output: {msg}").into());
+        }
+    };
+    *CAPTURE_CLASS.lock().map_err(|e| e.to_string())? = Some(class);
+    Ok(())
+}
+
+/// Gives `System.out` and `System.err` back.
+pub fn  stop_capture(jvm: &JavaVM) -> Result<(), Box<dyn std::error::Error>>
+{
+    let Some(class) = CAPTURE_CLASS.lock().map_err(|e| e.to_string())?.take() else { return Ok(()) };
+    let mut env = jvm.attach_current_thread()?;
+    env.call_static_method(<&JClass>::from(class.as_obj()), "uninstall", "()V", &[])?;
+    Ok(())
+}
+
+/// Starts buffering the current thread's output, if output is captured.
+pub fn  begin_capture(env: &mut JNIEnv)
+{
+    if let Some(class) = capture_class()
+        && env.call_static_method(<&JClass>::from(class.as_obj()), "begin", "()V", &[]).is_err()
+    {
+        let _ = env.exception_clear();
+    }
+}
+
+/// Everything the current thread printed since `begin_capture`.
+pub fn  end_capture(env: &mut JNIEnv) -> String
+{
+    let Some(class) = capture_class() else { return String::new() };
+    let result = env.with_local_frame(4, |env| -> Result<String, jni::errors::Error>
+    {
+        let s = env.call_static_method(<&JClass>::from(class.as_obj()), "end", "()Ljava/lang/String;", &[])?.l()?;
+        Ok(env.get_string(&JString::from(s))?.into())
+    });
+    result.unwrap_or_else(|_|
+    {
+        let _ = env.exception_clear();
+        String::new()
+    })
+}
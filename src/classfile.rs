@@ -0,0 +1,290 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+
+pub struct ClassInfo
+{
+    pub name:           String,
+    pub source_file:    Option<String>,
+    pub refs:           HashSet<String>,
+}
+
+pub struct Reader<'a>
+{
+    pub buf: &'a [u8],
+    pub pos: usize,
+}
+
+impl<'a> Reader<'a>
+{
+    #[inline(always)]
+    pub fn  bytes(&mut self, n: usize) -> Option<&'a [u8]>
+    {
+        let out = self.buf.get(self.pos..self.pos + n)?;
+        self.pos += n;
+        Some(out)
+    }
+    #[inline(always)]
+    pub fn  u2(&mut self) -> Option<u16>
+    {
+        self.bytes(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+    #[inline(always)]
+    pub fn  u4(&mut self) -> Option<u32>
+    {
+        self.bytes(4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+enum Constant
+{
+    Utf8(String),
+    Class(u16),
+    NameAndType(u16),
+    MethodType(u16),
+    Other,
+}
+
+/// Collects every `L<name>;` type embedded in a field or method descriptor.
+fn  descriptor_refs(desc: &str, out: &mut HashSet<String>)
+{
+    let mut rest = desc;
+    while let Some(start) = rest.find('L')
+    {
+        let tail = &rest[start + 1..];
+        match tail.find(';')
+        {
+            Some(end) =>
+            {
+                out.insert(tail[..end].to_string());
+                rest = &tail[end + 1..];
+            }
+            None => break,
+        }
+    }
+}
+
+/// Strips array markers so `[[Lcom/x/Foo;` resolves to `com/x/Foo`.
+fn  class_ref(name: &str) -> Option<String>
+{
+    let trimmed = name.trim_start_matches('[');
+    if trimmed.len() == name.len()
+    {
+        return Some(name.to_string());
+    }
+    trimmed.strip_prefix('L')
+        .and_then(|x| x.strip_suffix(';'))
+        .map(str::to_string)
+}
+
+/// Parses the constant pool of a `.class` file and returns the class name,
+/// its `SourceFile` attribute and every class it references.
+pub fn  parse_class(buf: &[u8]) -> Option<ClassInfo>
+{
+    let mut r = Reader { buf, pos: 0 };
+    if r.u4()? != 0xCAFEBABE
+    {
+        return None;
+    }
+    r.u2()?;
+    r.u2()?;
+    let count = r.u2()? as usize;
+    let mut pool: Vec<Constant> = Vec::with_capacity(count);
+    pool.push(Constant::Other);
+    while pool.len() < count
+    {
+        let tag = r.bytes(1)?[0];
+        let entry = match tag
+        {
+            1 =>
+            {
+                let len = r.u2()? as usize;
+                Constant::Utf8(String::from_utf8_lossy(r.bytes(len)?).into_owned())
+            }
+            7 => Constant::Class(r.u2()?),
+            12 =>
+            {
+                r.u2()?;
+                Constant::NameAndType(r.u2()?)
+            }
+            16 => Constant::MethodType(r.u2()?),
+            3 | 4 | 9 | 10 | 11 | 17 | 18 =>
+            {
+                r.bytes(4)?;
+                Constant::Other
+            }
+            5 | 6 =>
+            {
+                r.bytes(8)?;
+                pool.push(Constant::Other);
+                Constant::Other
+            }
+            8 | 19 | 20 =>
+            {
+                r.bytes(2)?;
+                Constant::Other
+            }
+            15 =>
+            {
+                r.bytes(3)?;
+                Constant::Other
+            }
+            _ => return None,
+        };
+        pool.push(entry);
+    }
+    let utf8 = |i: u16| match pool.get(i as usize)
+    {
+        Some(Constant::Utf8(s)) => Some(s.as_str()),
+        _ => None,
+    };
+
+    let mut refs = HashSet::new();
+    for c in &pool
+    {
+        match c
+        {
+            Constant::Class(i) =>
+            {
+                if let Some(name) = utf8(*i).and_then(class_ref)
+                {
+                    refs.insert(name);
+                }
+            }
+            Constant::NameAndType(i) | Constant::MethodType(i) =>
+            {
+                if let Some(desc) = utf8(*i)
+                {
+                    descriptor_refs(desc, &mut refs);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    r.u2()?;
+    let name = match pool.get(r.u2()? as usize)
+    {
+        Some(Constant::Class(i)) => utf8(*i)?.to_string(),
+        _ => return None,
+    };
+    r.u2()?;
+    let interfaces = r.u2()? as usize;
+    r.bytes(interfaces * 2)?;
+
+    // Field and method descriptors live outside the constant pool entries above.
+    for _ in 0..2
+    {
+        let members = r.u2()?;
+        for _ in 0..members
+        {
+            r.u2()?;
+            r.u2()?;
+            if let Some(desc) = utf8(r.u2()?)
+            {
+                descriptor_refs(desc, &mut refs);
+            }
+            let attrs = r.u2()?;
+            for _ in 0..attrs
+            {
+                r.u2()?;
+                let len = r.u4()? as usize;
+                r.bytes(len)?;
+            }
+        }
+    }
+
+    let mut source_file = None;
+    let attrs = r.u2()?;
+    for _ in 0..attrs
+    {
+        let attr_name = utf8(r.u2()?);
+        let len = r.u4()? as usize;
+        let data = r.bytes(len)?;
+        if attr_name == Some("SourceFile") && len == 2
+        {
+            source_file = utf8(u16::from_be_bytes([data[0], data[1]])).map(str::to_string);
+        }
+    }
+    refs.remove(&name);
+    Some(ClassInfo { name, source_file, refs })
+}
+
+#[inline(always)]
+pub fn  read_class(p: &PathBuf) -> Option<ClassInfo>
+{
+    parse_class(&fs::read(p).ok()?)
+}
+
+#[cfg(test)]
+pub mod tests
+{
+    use super::*;
+    use std::process::Command;
+
+    /// Compiles `sources`, `(path, code)` pairs, into a fresh directory for
+    /// the test `name` and returns it.
+    pub fn  javac(name: &str, sources: &[(&str, &str)]) -> PathBuf
+    {
+        let dir = std::env::temp_dir().join(format!("lvjb-test-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut files = Vec::new();
+        for (path, code) in sources
+        {
+            let file = dir.join("src").join(path);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(&file, code).unwrap();
+            files.push(file);
+        }
+        let bin = dir.join("bin");
+        let status = Command::new("javac").arg("-g").arg("-d").arg(&bin).args(&files).status().expect("javac should be on PATH");
+        assert!(status.success(), "javac failed for {name}");
+        bin
+    }
+
+    #[test]
+    fn  descriptors_and_array_names()
+    {
+        let mut refs = HashSet::new();
+        descriptor_refs("(I[Lcom/x/A;JLjava/util/List;)[[Lcom/x/B;", &mut refs);
+        assert_eq!(refs, HashSet::from(["com/x/A".to_string(), "java/util/List".to_string(), "com/x/B".to_string()]));
+        assert_eq!(class_ref("com/x/A").as_deref(), Some("com/x/A"));
+        assert_eq!(class_ref("[[Lcom/x/A;").as_deref(), Some("com/x/A"));
+        assert_eq!(class_ref("[I"), None);
+    }
+
+    #[test]
+    fn  reads_the_constant_pool_of_javac_output()
+    {
+        let bin = javac("classfile", &[
+            ("com/x/Base.java", "package com.x; public class Base {}"),
+            ("com/x/Bar.java", "package com.x; public class Bar {}"),
+            ("com/x/Qux.java", "package com.x; public class Qux {}"),
+            ("com/x/Foo.java", "package com.x;
+                import java.util.function.Supplier;
+                public class Foo extends Base {
+                    static final long BIG = 1L << 40;
+                    static final double PI = 3.14159;
+                    Bar[][] grid = new Bar[2][];
+                    java.util.List<String> names(Qux q) { return java.util.List.of(\"a\" + BIG + PI); }
+                    Supplier<Bar> make() { return Bar::new; }
+                }"),
+        ]);
+        let info = read_class(&bin.join("com/x/Foo.class")).expect("Foo.class should parse");
+        assert_eq!(info.name, "com/x/Foo");
+        assert_eq!(info.source_file.as_deref(), Some("Foo.java"));
+        for name in ["com/x/Base", "com/x/Bar", "com/x/Qux", "java/util/List", "java/util/function/Supplier"]
+        {
+            assert!(info.refs.contains(name), "{name} missing from {:?}", info.refs);
+        }
+        assert!(!info.refs.contains("com/x/Foo"));
+        let _ = fs::remove_dir_all(bin.parent().unwrap());
+    }
+
+    #[test]
+    fn  rejects_other_files()
+    {
+        assert!(parse_class(b"not a class").is_none());
+        assert!(parse_class(&[0xCA, 0xFE, 0xBA, 0xBE, 0, 0, 0, 52, 0, 3, 1, 0, 9, b'x']).is_none());
+    }
+}
@@ -1,18 +1,24 @@
 use std::{fs, fs::write};
-use std::io::{stderr, copy, Write};
 use std::path::PathBuf;
+use std::collections::HashMap;
 use crate::config::*;
 use crate::fs::*;
 use crate::cache::*;
 use crate::incremental::*;
 use crate::spawn::*;
 use crate::jvm::*;
-use std::thread;
+use crate::testrunner::*;
+use crate::report::*;
 use jni::objects::*;
 use jni::*;
 use std::process::Command;
-use std::sync::{Arc, Mutex};
-use reqwest::blocking::get;
+use crate::scheduler::*;
+use crate::capture::*;
+use crate::coverage::*;
+use crate::filter::*;
+use crate::maven::*;
+use crate::daemon::{in_daemon, guard_exit, exit_status};
+use crate::watch::watching;
 
 pub const ORANGE: &str = "\x1b[33m";
 pub const GREEN: &str = "\x1b[32m";
@@ -42,21 +48,39 @@ pub fn  init(config: &Config) -> Result<(), Box<dyn std::error::Error>>
 }
 
 #[inline(always)]
-pub fn  initpkg(mut s: String, config: &Config) -> Result<(), Box<dyn std::error::Error>>
+pub fn  initpkg(s: String, config: &Config) -> Result<(), Box<dyn std::error::Error>>
 {
-    let p: PathBuf = forge_sys_path(&class_to_path(&mut s), config, PathType::SRC);
+    let p: PathBuf = forge_sys_path(&class_to_path(&s), config, PathType::SRC);
     fs::create_dir_all(&p)?;
     Ok(())
 }
 
+/// First argument after the command that is neither a flag nor `-j`'s
+/// value, before a `--`.
+pub fn  operand(args: &[String]) -> Option<&String>
+{
+    let mut it = args.iter().skip(2).take_while(|x| *x != "--");
+    while let Some(arg) = it.next()
+    {
+        match arg.as_str()
+        {
+            "-j" => { it.next(); }
+            x if x.starts_with('-') => (),
+            _ => return Some(arg),
+        }
+    }
+    None
+}
+
 pub fn build(pkg: Option<&String>, config: &mut Config) -> Result<(), Box<dyn std::error::Error>>
 {
+    verify_libs(config)?;
     let mut f: bool = false;
     let pkpath = match pkg
     {
         Some(name) =>
         {
-            if name.as_str() == "all" || config.incremental == false
+            if name.as_str() == "all" || !config.incremental
             {
                 PathBuf::from(&config.paths.src)
             }
@@ -71,24 +95,32 @@ pub fn build(pkg: Option<&String>, config: &mut Config) -> Result<(), Box<dyn st
             forge_sys_path(&config.paths.src_nopkg, config, PathType::SRC)
         },
     };
-    let mut files: Vec<PathBuf> = match config.incremental
-    {
-        true => fetch_files_under(&pkpath, &config.src_ext)
-            .into_iter()
-            .filter(|x| check_incremental(x, config))
-            .collect(),
-        false => fetch_files_under(&pkpath, &config.src_ext),
-    };
+    let mut files: Vec<PathBuf> = fetch_files_under(&pkpath, &config.src_ext);
     if f
     {
         let default_path = forge_sys_path(&config.paths.src_nopkg, config, PathType::SRC);
-        let default_files = fetch_files_under(&default_path, &config.src_ext)
-            .into_iter()
-            .filter(|x| check_incremental(x, config));
-        files.extend(default_files);
+        files.extend(fetch_files_under(&default_path, &config.src_ext));
+    }
+    let pruned = prune_deleted(config);
+    let mut staged: HashMap<String, String> = HashMap::new();
+    if config.incremental
+    {
+        files = dirty_files(files, config, &mut staged);
+    }
+    else
+    {
+        for file in &files
+        {
+            check_incremental(file, config, &mut staged);
+        }
     }
 
     spawn_compilation_command(&files, config)?;
+    if files.is_empty() && pruned.is_empty()
+    {
+        return Ok(());
+    }
+    commit_staged(staged, config);
 
     if let Err(e) = config.cache.write()
     {
@@ -115,8 +147,18 @@ pub fn run(p: Option<&String>, config: &Config, jvm: &JavaVM, output: bool) -> R
 
     let mut env = jvm.attach_current_thread()?;
 
-    let class_name = pkg.replace(".", "/");
-    let class = env.find_class(&class_name)?;
+    let loader = class_loader(&mut env, config);
+    let class = match loader.and_then(|l| load_class(&mut env, &l, pkg))
+    {
+        Ok(x) => x,
+        Err(jni::errors::Error::JavaException) =>
+        {
+            let msg = describe_exception(&mut env)
+                .map_err(|e| format!("[RUNNER] {e}"))?;
+            return Err(format!("{RED}[EXCEPTION]{RESET} {msg}").into());
+        }
+        Err(e) => return Err(e.into()),
+    };
 
     let empty_args = Vec::new();
     let args_list = config.args.runtime.as_ref().unwrap_or(&empty_args);
@@ -130,27 +172,48 @@ pub fn run(p: Option<&String>, config: &Config, jvm: &JavaVM, output: bool) -> R
         env.set_object_array_element(&args_array, i as i32, jstr)?;
     }
 
+    // The daemon's and the watcher's VM outlive the program, `System.exit`
+    // must not end them.
+    let outlived = in_daemon() || watching();
+    let guarded = outlived && guard_exit(jvm, config, true)?;
+    if outlived && !guarded
+    {
+        let host = if in_daemon() { "the daemon" } else { "lvjb watch" };
+        eprintln!("{ORANGE}[RUNNER]{RESET} This JDK can't intercept System.exit, a program calling it stops {host}");
+    }
+    if guarded
+    {
+        // Loading the guard made its loader the context class loader.
+        let loader = env.call_method(&class, "getClassLoader", "()Ljava/lang/ClassLoader;", &[])?.l()?;
+        let thread = env.call_static_method("java/lang/Thread", "currentThread", "()Ljava/lang/Thread;", &[])?.l()?;
+        env.call_method(&thread, "setContextClassLoader", "(Ljava/lang/ClassLoader;)V", &[JValue::Object(&loader)])?;
+    }
     let result = env.call_static_method(
         class,
         "main",
         "([Ljava/lang/String;)V",
         &[JValue::Object(&JObject::from(args_array))],
     );
+    // Cleared before the guard is removed, JNI calls can't have it pending.
+    let thrown = match result
+    {
+        Err(jni::errors::Error::JavaException) => Some(describe_exception(&mut env).map_err(|e| format!("[RUNNER] {e}"))),
+        _ => None,
+    };
+    if guarded
+    {
+        guard_exit(jvm, config, false)?;
+    }
 
-    if let Err(jni::errors::Error::JavaException) = result
+    if let Some(msg) = thrown
     {
-        if env.exception_check()?
+        let msg = msg?;
+        match exit_status(&msg).filter(|_| guarded)
         {
-            let exception = env.exception_occurred()?;
-            env.exception_clear()?;
-
-            let jstr = env.call_method(exception, "toString", "()Ljava/lang/String;", &[])?;
-            let msg_obj = jstr.l()?;
-            let msg: String = env.get_string(&JString::from(msg_obj))?.into();
-
-            return Err(format!("{RED}[EXCEPTION]{RESET} {msg}").into());
+            Some(0) => (),
+            Some(status) => return Err(format!("{RED}[RUNNER]{RESET} Exited with status {status}").into()),
+            None => return Err(format!("{RED}[EXCEPTION]{RESET} {msg}").into()),
         }
-        return Err("[RUNNER] Java exception occurred, but couldn't get details".into());
     }
     else
     {
@@ -163,89 +226,108 @@ pub fn run(p: Option<&String>, config: &Config, jvm: &JavaVM, output: bool) -> R
     Ok(())
 }
 
-pub fn test(config: &mut Config) -> Result<(), Box<dyn std::error::Error>>
+pub fn test(config: &mut Config, options: &TestOptions) -> Result<Summary, Box<dyn std::error::Error>>
 {
+    verify_libs(config)?;
     let pkpath = PathBuf::from(&config.paths.test);
+    let support = ensure_test_support(config)?;
 
+    let mut staged: HashMap<String, String> = HashMap::new();
+    let all = fetch_files_under(&pkpath, &config.src_ext);
+    let selected = select_sources(&all, &options.filter, config);
+    if selected.len() < all.len()
+    {
+        eprintln!("{ORANGE}[TESTRUNNER]{RESET} Building {} of {} test source(s)", selected.len(), all.len());
+    }
+    let mut sources = selected.clone();
+    sources.extend(support);
     let files: Vec<PathBuf> = if config.incremental
     {
-        fetch_files_under(&pkpath, &config.src_ext)
+        let (files, left_out): (Vec<PathBuf>, Vec<PathBuf>) = dirty_files(sources, config, &mut staged)
             .into_iter()
-            .filter(|x| check_incremental(x, config))
-            .collect()
+            .partition(|x| !x.starts_with(&pkpath) || selected.contains(x));
+        // Unselected tests that depend on a changed source are rebuilt next time.
+        for file in left_out
+        {
+            let key = file.to_string_lossy().to_string();
+            staged.remove(&key);
+            config.cache.files.remove(&key);
+        }
+        files
     }
     else
     {
-        fetch_files_under(&pkpath, &config.src_ext)
+        for file in &sources
+        {
+            check_incremental(file, config, &mut staged);
+        }
+        sources
     };
 
     spawn_compilation_command(&files, config)?;
+    if !files.is_empty()
+    {
+        commit_staged(staged, config);
+        config.cache.write()?;
+    }
 
-    let all = fetch_files_under(&pkpath, &config.src_ext);
-
-    config.cache.write()?;
+    // Coverage may add VM options or instrumented classes for the tests only.
+    let mut run_config = config.clone();
+    let coverage = match options.coverage
+    {
+        true => Some(Coverage::prepare(config, &mut run_config)?),
+        false => None,
+    };
+    let jvm = shared_jvm(&run_config)?;
+    if let Some(c) = &coverage
+    {
+        c.recorder().start(jvm)?;
+    }
+    let guarded = config.test_isolation != ISOLATION_PROCESS;
+    if guarded && !exit_guard(jvm, config, true)?
+    {
+        eprintln!("{ORANGE}[TESTRUNNER]{RESET} This JDK can't intercept System.exit, set test_isolation = \"{ISOLATION_PROCESS}\" if tests call it");
+    }
 
-    let jvm = spawn_jvm(config)?;
-    let jvm = std::sync::Arc::new(jvm);
+    let sources: HashMap<String, String> = selected.iter()
+        .filter_map(|x| test_class_name(x, config)
+            .filter(|c| options.filter.may_select(c, &fs::read_to_string(x).unwrap_or_default()))
+            .map(|c| (c, x.to_string_lossy().to_string())))
+        .collect();
+    let mut classes: Vec<String> = sources.keys().cloned().collect();
+    classes.sort();
+    if options.changed
+    {
+        let total = classes.len();
+        classes.retain(|c| test_changed(c, config));
+        eprintln!("{ORANGE}[TESTRUNNER]{RESET} --changed: {} of {total} test class(es) affected by changes", classes.len());
+    }
+    start_capture(jvm, config)?;
+    let run = run_pool(classes, &run_config, jvm, options);
+    stop_capture(jvm)?;
+    let run = run?;
 
-    let ok_tests: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::with_capacity(all.len())));
-    let allowed_n = thread::available_parallelism().map(|n| n.get()).unwrap_or(2);
-    for chunk in all.chunks(allowed_n)
+    if guarded
+    {
+        exit_guard(jvm, config, false)?;
+    }
+    for class in run.durations.keys()
     {
-        let mut handles = Vec::new();
-        for file in chunk.to_vec()
+        let failed = run.results.iter().any(|r| &r.class == class && matches!(r.outcome, Outcome::Failed(_)));
+        match (failed, sources.get(class))
         {
-            let src = config.paths.test.clone();
-            let bin = config.paths.bin.clone();
-            let ext = config.src_ext.clone();
-            let conf = config.clone();
-            let jvmp = Arc::clone(&jvm);
-            let ok_vec = Arc::clone(&ok_tests);
-            handles.push(thread::spawn(move ||
-            {
-                if let Ok(relative) = file.strip_prefix(&src)
-                {
-                    let mut new_path = PathBuf::from(&bin);
-                    new_path.push(relative);
-                    if new_path.extension().map(|e| *e == *ext).unwrap_or(false)
-                    {
-                        new_path.set_extension("");
-                    }
-                    let class_name = new_path.strip_prefix(&bin)
-                        .unwrap_or(&new_path)
-                        .with_extension("")
-                        .to_string_lossy()
-                        .replace("/", ".")
-                        .replace("\\", ".");
-                    if let Err(e) = run(Some(&class_name), &conf, &jvmp, false)
-                    {
-                        eprintln!("{RED}[TEST FAILED]{RESET} {}: {e}", class_name);
-                        let _ = stderr().flush();
-                    }
-                    else
-                    {
-                        ok_vec.lock()
-                        .expect(&format!("{RED}[TESTRUNNER]{RESET} Failed to lock test result vector: {}", std::io::Error::last_os_error()))
-                        .push(class_name);
-                        let _ = stderr().flush();
-                    }
-                }
-            }));
-        }
-        for handle in handles {
-            match handle.join()
-            {
-                Ok(_) => (),
-                Err(_) => return Err(format!("{RED}[TESTRUNNER]{RESET} Failed to join handles").into()),
-            }
+            (false, Some(src)) => { config.cache.test_sources.insert(class.clone(), touched_sources(src, config)); }
+            _ => { config.cache.test_sources.remove(class); }
         }
     }
+    config.cache.test_durations.extend(run.durations);
+    config.cache.write()?;
 
-    let passed = match ok_tests.lock()
-    {
-        Ok(x) => x,
-        Err(e) => return Err(format!("{RED}[TESTRUNNER]{RESET} Failed to lock test result vector: {}", e).into()),
-    };
+    let (results, not_run) = (run.results, run.not_run);
+    let passed: Vec<String> = results.iter()
+        .filter(|x| x.outcome == Outcome::Passed)
+        .map(TestResult::name)
+        .collect();
     if passed.is_empty()
     {
         eprintln!("{RED}[TESTRUNNER]{RESET} No tests passed.");
@@ -256,15 +338,47 @@ pub fn test(config: &mut Config) -> Result<(), Box<dyn std::error::Error>>
         for ok in passed.iter()
         {
             eprint!("{} ", ok);
-    }
+        }
         eprintln!();
     }
-    Ok(())
+    write_reports(&options.reports, &results)?;
+    if let Some(c) = &coverage
+    {
+        report_coverage(&c.finish(jvm, config)?, config)?;
+    }
+
+    let mut summary = Summary::of(results.iter());
+    summary.not_run = not_run;
+    print_table(&results, &summary);
+    if not_run > 0
+    {
+        eprintln!("{ORANGE}[TESTRUNNER]{RESET} --fail-fast: stopped after the first failure, {not_run} class(es) not run");
+    }
+    Ok(summary)
 }
 
-#[inline(always)]
-pub fn  clean(config: &Config) -> Result<(), Box<dyn std::error::Error>>
+pub fn  clean(pkg: Option<&String>, config: &mut Config) -> Result<(), Box<dyn std::error::Error>>
 {
+    if let Some(name) = pkg
+    {
+        let target = PathBuf::from(class_to_path(name));
+        let mut sources: Vec<String> = config.cache.outputs.keys()
+            .filter(|x| source_package(&PathBuf::from(x), config).is_some_and(|p| p.starts_with(&target)))
+            .cloned()
+            .collect();
+        sources.sort();
+        if sources.is_empty()
+        {
+            return Err(format!("{RED}[CLEAN]{RESET} No compiled sources recorded for '{name}'").into());
+        }
+        for src in &sources
+        {
+            forget_source(src, config);
+            eprintln!("{GREEN}[CLEAN]{RESET} {src}");
+        }
+        config.cache.write()?;
+        return Ok(());
+    }
     let files = fetch_files_under(&PathBuf::from(&config.paths.bin), &"class".to_string());
     for file in files
     {
@@ -294,17 +408,14 @@ pub fn  docgen(s: &str, config: &Config)
 #[inline(always)]
 pub fn curl(url: &String, config: &mut Config) -> Result<(), Box<dyn std::error::Error>>
 {
-    eprintln!("{ORANGE}[FETCHING]{RESET} {}", url);
-    let response = get(url)?;
-    let filename = url.rsplit('/').next().ok_or_else(||
+    let filename = url.rsplit('/').next().filter(|x| !x.is_empty()).ok_or_else(||
     {
         format!("{RED}[FETCHER]{RESET} Invalid URL")
     })?;
-    let dest_path: PathBuf = forge_sys_path(filename, config, PathType::LIB);
-    let mut dest = fs::File::create(dest_path)?;
-    let mut content = response;
-    copy(&mut content, &mut dest)?;
+    fs::create_dir_all(&config.paths.lib)?;
+    let hash = install(url, filename, &forge_sys_path(filename, config, PathType::LIB), config)?;
     eprintln!("{GREEN}[FETCHED]{RESET} {}", filename);
+    config.cache.libs.insert(filename.to_string(), hash);
     config.cache.url_libs.push(url.to_owned());
     config.cache.write()?;
     Ok(())
@@ -313,6 +424,28 @@ pub fn curl(url: &String, config: &mut Config) -> Result<(), Box<dyn std::error:
 
 pub fn  release(config: &mut Config) -> Result<(), Box<dyn std::error::Error>> {
     build(Some(&"all".to_string()), config)?;
+    let mut sources: Vec<&String> = config.cache.outputs.keys().collect();
+    sources.sort();
+    let mut broken = Vec::new();
+    for src in sources
+    {
+        for class in missing_outputs(src, config)
+        {
+            broken.push(format!("{class} (missing, from {src})"));
+        }
+        for class in modified_outputs(src, config)
+        {
+            broken.push(format!("{class} (modified, from {src})"));
+        }
+    }
+    if !broken.is_empty()
+    {
+        for b in &broken
+        {
+            eprintln!("{RED}[RELEASE]{RESET} {b}");
+        }
+        return Err(format!("{RED}[RELEASE]{RESET} {} class file(s) don't match the last build, run 'lvjb build all --re'", broken.len()).into());
+    }
     let combined_hash: u64 = config.cache.files
         .values()
         .filter_map(|x| x.parse::<u64>().ok())
@@ -321,7 +454,7 @@ pub fn  release(config: &mut Config) -> Result<(), Box<dyn std::error::Error>> {
         {
             x.as_ref()
             .and_then(|(_, s)| s.parse::<u64>().ok())
-            .map_or(false, |n| n == combined_hash)
+            .is_some_and(|n| n == combined_hash)
         }
     )
     {
@@ -337,7 +470,7 @@ pub fn  release(config: &mut Config) -> Result<(), Box<dyn std::error::Error>> {
     let manifest_path = PathBuf::from("MANIFEST.MF");
     write(&manifest_path, manifest_content)?;
     let out = format!("{}-{}.jar", &config.jar, &config.version);
-    let jar_path = forge_sys_path(&out, &config, PathType::RELEASES);
+    let jar_path = forge_sys_path(&out, config, PathType::RELEASES);
     let status = Command::new("jar")
         .arg("cfm")
         .arg(&jar_path)
@@ -370,20 +503,47 @@ pub fn  help()
     println!("{ORANGE}Available Commands:{RESET}");
     println!("  init                       Initializes project structure and config");
     println!("  initpkg <pkg>              Creates folder tree under src/ for given package");
-    println!("  build [pkg|all] [--re]     Builds Java sources (incrementally unless --re)");
-    println!("  test                       Compiles and runs test files (via JNI, parallel)");
+    println!("  build [pkg|all] [--re] [-j N]  Builds Java sources (incrementally unless --re, N parallel javacs with parallel_javac)");
+    println!("  test [pattern] [--report <fmt>] [--fail-fast] [-j N]  Compiles and runs @Test methods / test programs (via JNI, N workers)");
     println!("  run [MainClass]            Runs specified Java class or entry_point from config");
-    println!("  clean                      Deletes all .class files and clears cache");
+    println!("  clean [pkg]                Deletes all .class files and clears cache, or only those of pkg");
     println!("  docgen <Class>             Generates Javadoc for specified class");
     println!("  curl <url>                 Downloads and registers remote JAR");
+    println!("  fetch                      Makes lib/ match [dependencies] in lvjb.toml and what they depend on");
+    println!("  tree                       Prints the resolved dependency tree and why each jar is in it");
+    println!("  lib [list]                 Lists the jars in lib/, where each came from and if it still matches its hash");
+    println!("  lib remove <name>          Removes a jar (or declared dependency) from lib/ and lvjb.lock");
+    println!("  lib update [name]          Moves dependencies to their newest version, downloads curl'd jars again");
+    println!("  lib outdated               Lists dependencies with newer versions in the repository");
+    println!("  watch [build|test|run]     Reruns the command whenever sources or lvjb.toml change");
+    println!("  daemon [stop|status]       Starts, stops or queries the warm JVM build daemon");
     println!("  release                    Builds JAR from entry_point and config values");
     println!("  help                       Displays this help message");
     println!();
     println!("{ORANGE}Quirks & Notes:{RESET}");
     println!("  - Always compiles default/ (no-package) sources, even if building a package.");
-    println!("  - test/ files are treated as standalone Java programs, no framework needed.");
+    println!("  - test/ classes run their @Test methods (lvjb.Test or JUnit's), or main() if they have none.");
+    println!("  - test_isolation = \"classloader\" or \"process\" in lvjb.toml runs each test method on its own.");
+    println!("  - test_timeout (seconds, [test_timeouts] per class) stops hung tests and reports a thread dump.");
+    println!("  - test <pattern> runs tests whose Class.method matches (substring or glob, --exact for whole names).");
+    println!("  - test --skip <pattern> leaves tests out, --include-tag <tag> runs only tests with that @Tag.");
+    println!("  - test --changed runs only test classes touching sources changed since they last passed.");
+    println!("  - [dependencies] \"group:artifact\" = \"version\" are fetched from repository (Maven Central by default).");
+    println!("  - Their POMs' compile/runtime dependencies come along; on a version conflict the nearest one wins.");
+    println!("  - Jars and POMs are cached in ~/.cache/lvjb and hardlinked into lib/; --offline only uses that cache.");
+    println!("  - Downloaded jars are checked against [checksums], lvjb.lock or .sha256/.sha1 files; build refuses modified ones.");
+    println!("  - test --retries <n> reruns failed tests, those passing on a retry are reported as flaky.");
+    println!("  - quarantine = [\"Class.method\", ...] in lvjb.toml: those tests run and report but don't fail the build.");
+    println!("  - test --coverage writes coverage/lcov.info (JaCoCo if its agent + cli jars are in lib/, else built-in).");
+    println!("  - test output is shown for failing tests only, --nocapture shows it for all.");
+    println!("  - test exits with code 2 when a test fails or errors (1 for build/runner errors).");
+    println!("  - test --report junit=<path> writes JUnit XML, --report tap[=<path>] writes TAP (stdout by default).");
+    println!("  - build/test accept --message-format=json for machine-readable diagnostics.");
+    println!("  - Set compiler = \"javax.tools\" in lvjb.toml to compile inside the embedded JVM.");
     println!("  - Classpath expansion supports wildcards like lib/*");
     println!("  - Incremental builds use fast xxh3 hashing (not timestamps).");
+    println!("  - Classes depending on a changed file are recompiled too (graph kept in lvjb.lock).");
+    println!("  - build prunes cache entries and .class files of deleted sources.");
     println!("  - Remote JARs via 'curl' are cached and reused.");
     println!("  - Release creates a JAR using 'jar' tool and Main-Class from config.");
     println!("  - If lvjb.toml or lvjb.lock doesn't exist, they’re auto-generated.");
@@ -0,0 +1,162 @@
+use crate::config::*;
+use crate::fs::*;
+use crate::jvm::*;
+use crate::diagnostics::*;
+use jni::objects::*;
+use jni::JNIEnv;
+use std::path::PathBuf;
+
+/// `compiler` value that selects the embedded javax.tools backend.
+pub const IN_PROCESS_COMPILER: &str = "javax.tools";
+
+fn  string_list<'a>(env: &mut JNIEnv<'a>, items: &[String]) -> Result<JObject<'a>, jni::errors::Error>
+{
+    let array = env.new_object_array(items.len() as i32, "java/lang/String", JObject::null())?;
+    for (i, item) in items.iter().enumerate()
+    {
+        let jstr = env.new_string(item)?;
+        env.set_object_array_element(&array, i as i32, jstr)?;
+    }
+    env.call_static_method(
+        "java/util/Arrays",
+        "asList",
+        "([Ljava/lang/Object;)Ljava/util/List;",
+        &[JValue::Object(&array)],
+    )?.l()
+}
+
+fn  java_string(env: &mut JNIEnv, obj: &JObject, method: &str, sig: &str, args: &[JValue]) -> Result<Option<String>, jni::errors::Error>
+{
+    let value = env.call_method(obj, method, sig, args)?.l()?;
+    if value.is_null()
+    {
+        return Ok(None);
+    }
+    Ok(Some(env.get_string(&JString::from(value))?.into()))
+}
+
+fn  read_diagnostic(env: &mut JNIEnv, diag: &JObject) -> Result<Diagnostic, jni::errors::Error>
+{
+    let kind = env.call_method(diag, "getKind", "()Ljavax/tools/Diagnostic$Kind;", &[])?.l()?;
+    let severity = match java_string(env, &kind, "name", "()Ljava/lang/String;", &[])?.as_deref()
+    {
+        Some("ERROR") => Severity::Error,
+        Some("WARNING") | Some("MANDATORY_WARNING") => Severity::Warning,
+        _ => Severity::Note,
+    };
+    let source = env.call_method(diag, "getSource", "()Ljava/lang/Object;", &[])?.l()?;
+    let file = if source.is_null()
+    {
+        None
+    }
+    else
+    {
+        java_string(env, &source, "getName", "()Ljava/lang/String;", &[])?
+    };
+    // javax.tools reports Diagnostic.NOPOS (-1) when a position is unknown.
+    let line = env.call_method(diag, "getLineNumber", "()J", &[])?.j()?;
+    let column = env.call_method(diag, "getColumnNumber", "()J", &[])?.j()?;
+    let message = java_string(env, diag, "getMessage", "(Ljava/util/Locale;)Ljava/lang/String;", &[JValue::Object(&JObject::null())])?;
+    Ok(Diagnostic
+    {
+        file,
+        line:       (line > 0).then_some(line as u64),
+        column:     (column > 0).then_some(column as u64),
+        severity,
+        message:    message.unwrap_or_default(),
+    })
+}
+
+/// Compiles `files` with `ToolProvider.getSystemJavaCompiler()` inside the
+/// shared JVM. Returns whether compilation succeeded and every diagnostic.
+pub fn  compile_in_process(files: &[PathBuf], config: &Config) -> Result<(bool, Vec<Diagnostic>), Box<dyn std::error::Error>>
+{
+    let jvm = shared_jvm(config)?;
+    let mut env = jvm.attach_current_thread()?;
+
+    let mut options: Vec<String> = Vec::new();
+    if !config.classpath.is_empty()
+    {
+        options.push("-cp".to_string());
+        options.push(expand_classpath(&config.classpath));
+    }
+    options.push("-d".to_string());
+    options.push(config.paths.bin.clone());
+    if let Some(x) = &config.args.compilation
+    {
+        options.extend(x.iter().cloned());
+    }
+    let sources: Vec<String> = files.iter().map(|x| x.to_string_lossy().to_string()).collect();
+
+    let result = env.with_local_frame(64, |env| -> Result<(bool, Vec<Diagnostic>), jni::errors::Error>
+    {
+        let compiler = env.call_static_method(
+            "javax/tools/ToolProvider",
+            "getSystemJavaCompiler",
+            "()Ljavax/tools/JavaCompiler;",
+            &[],
+        )?.l()?;
+        if compiler.is_null()
+        {
+            return Err(jni::errors::Error::NullPtr("getSystemJavaCompiler"));
+        }
+        let collector = env.new_object("javax/tools/DiagnosticCollector", "()V", &[])?;
+        let manager = env.call_method(
+            &compiler,
+            "getStandardFileManager",
+            "(Ljavax/tools/DiagnosticListener;Ljava/util/Locale;Ljava/nio/charset/Charset;)Ljavax/tools/StandardJavaFileManager;",
+            &[JValue::Object(&collector), JValue::Object(&JObject::null()), JValue::Object(&JObject::null())],
+        )?.l()?;
+        let source_list = string_list(env, &sources)?;
+        let units = env.call_method(
+            &manager,
+            "getJavaFileObjectsFromStrings",
+            "(Ljava/lang/Iterable;)Ljava/lang/Iterable;",
+            &[JValue::Object(&source_list)],
+        )?.l()?;
+        let option_list = string_list(env, &options)?;
+        let task = env.call_method(
+            &compiler,
+            "getTask",
+            "(Ljava/io/Writer;Ljavax/tools/JavaFileManager;Ljavax/tools/DiagnosticListener;Ljava/lang/Iterable;Ljava/lang/Iterable;Ljava/lang/Iterable;)Ljavax/tools/JavaCompiler$CompilationTask;",
+            &[
+                JValue::Object(&JObject::null()),
+                JValue::Object(&manager),
+                JValue::Object(&collector),
+                JValue::Object(&option_list),
+                JValue::Object(&JObject::null()),
+                JValue::Object(&units),
+            ],
+        )?.l()?;
+        let boxed = env.call_method(&task, "call", "()Ljava/lang/Boolean;", &[])?.l()?;
+        let ok = env.call_method(&boxed, "booleanValue", "()Z", &[])?.z()?;
+
+        let list = env.call_method(&collector, "getDiagnostics", "()Ljava/util/List;", &[])?.l()?;
+        let n = env.call_method(&list, "size", "()I", &[])?.i()?;
+        let mut diagnostics = Vec::with_capacity(n as usize);
+        for i in 0..n
+        {
+            let diag = env.with_local_frame(16, |env| -> Result<Diagnostic, jni::errors::Error>
+            {
+                let diag = env.call_method(&list, "get", "(I)Ljava/lang/Object;", &[JValue::Int(i)])?.l()?;
+                read_diagnostic(env, &diag)
+            })?;
+            diagnostics.push(diag);
+        }
+        env.call_method(&manager, "close", "()V", &[])?;
+        Ok((ok, diagnostics))
+    });
+
+    match result
+    {
+        Ok(x) => Ok(x),
+        Err(jni::errors::Error::JavaException) =>
+        {
+            let msg = describe_exception(&mut env)?;
+            Err(format!("javax.tools threw {msg}").into())
+        }
+        Err(jni::errors::Error::NullPtr(_)) =>
+            Err("no system Java compiler available, the JVM lvjb links against must be a JDK".into()),
+        Err(e) => Err(e.into()),
+    }
+}
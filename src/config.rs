@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 use crate::cache::*;
+use crate::maven::MAVEN_CENTRAL;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use toml;
 
@@ -35,7 +37,7 @@ impl Default for PathCnf
     }
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 #[serde(default)]
 pub struct ArgCnf
 {
@@ -45,20 +47,6 @@ pub struct ArgCnf
     pub jvm:         Option<Vec<String>>,
 }
 
-impl Default for ArgCnf
-{
-    fn default() -> Self
-    {
-        Self
-        {
-            compilation:    None,
-            runtime:        None,
-            test:           None,
-            jvm:            None,
-        }
-    }
-}
-
 #[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(default)]
 pub struct Config
@@ -69,18 +57,47 @@ pub struct Config
     pub src_ext:            String,
     pub classpath:          Vec<String>,
     pub incremental:        bool,
+    pub jobs:               usize,
+    /// Compile independent packages in parallel javac processes.
+    pub parallel_javac:     bool,
     pub paths:              PathCnf,
     pub args:               ArgCnf,
+    pub test_timeouts:      HashMap<String, u64>,
     pub pre_build_cmds:     Vec<String>,
     pub post_build_cmds:    Vec<String>,
     pub log_level:          u8,
+    pub message_format:     String,
+    pub test_isolation:     String,
+    pub test_timeout:       u64,
+    /// Tests (`Class.method`, or `Class` for all of them) whose failures are
+    /// reported but don't fail the build.
+    pub quarantine:         Vec<String>,
+    /// Maven repository `[dependencies]` are fetched from.
+    pub repository:         String,
+    /// `"group:artifact" = "version"` jars `lvjb fetch` keeps in `paths.lib`.
+    pub dependencies:       BTreeMap<String, String>,
+    /// Pinned `sha256:<hex>` or `sha1:<hex>` of jars, by file name.
+    pub checksums:          BTreeMap<String, String>,
+    /// Set by `--offline`: artifacts only come from the shared cache.
+    #[serde(skip)]
+    pub offline:            bool,
     pub version:            String,
+    #[serde(skip, default = "Cache::load_or_init")]
     pub cache:              Cache,
 
 }
 
 impl Default for Config {
     fn default() -> Self
+    {
+        Config::with_cache(Cache::load_or_init())
+    }
+}
+
+impl Config
+{
+    /// The default settings, with `cache` instead of `lvjb.lock`.
+    pub fn  with_cache(cache: Cache) -> Self
     {
         Config
         {
@@ -90,28 +107,27 @@ impl Default for Config {
             src_ext:            "java".to_string(),
             classpath:          vec!["bin".to_string(), "lib/*".to_string()],
             incremental:        true,
+            jobs:               0,
+            parallel_javac:     false,
             paths:              PathCnf::default(),
             args:               ArgCnf::default(),
+            test_timeouts:      HashMap::new(),
             pre_build_cmds:     Vec::new(),
             post_build_cmds:    Vec::new(),
             log_level:          0,
+            message_format:     "human".to_string(),
+            test_isolation:     "shared".to_string(),
+            test_timeout:       0,
+            quarantine:         Vec::new(),
+            repository:         MAVEN_CENTRAL.to_string(),
+            dependencies:       BTreeMap::new(),
+            checksums:          BTreeMap::new(),
+            offline:            false,
             version:            "0.0.1".to_string(),
-            cache:              match Cache::load()
-                                {
-                                    Ok(x) => x,
-                                    Err(_) =>
-                                    {
-                                        let t = Cache::default();
-                                        let _ = &t.write();
-                                        t
-                                    },
-                                }
+            cache,
         }
     }
-}
 
-impl Config
-{
     #[inline(always)]
     pub fn load() -> Result<Self, Box<dyn std::error::Error>>
     {
@@ -0,0 +1,417 @@
+use crate::config::*;
+use crate::fs::*;
+use crate::classfile::*;
+use crate::instrument::*;
+use crate::jvm::*;
+use crate::spawn::{GREEN, ORANGE, RED, RESET};
+use jni::objects::*;
+use jni::JavaVM;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+/// Where `--coverage` leaves its files: `lcov.info`, and the instrumented
+/// classes or JaCoCo's data.
+pub const COVERAGE_DIR: &str = "coverage";
+
+/// Hands the recorder to `test-worker` processes.
+pub const COVERAGE_ENV: &str = "LVJB_COVERAGE";
+
+/// System property holding the `boolean[]` of probe hits, so the copies of
+/// `lvjb.Coverage` in every test class loader record into the same array.
+const HITS_PROPERTY: &str = "lvjb.coverage";
+
+/// Runtime side of the built-in probes, written next to the annotations.
+pub const COVERAGE: &str = "package lvjb;
+
+public final class Coverage {
+    private static final boolean[] HITS = hits();
+
+    private static boolean[] hits() {
+        Object hits = System.getProperties().get(\"lvjb.coverage\");
+        return hits instanceof boolean[] ? (boolean[]) hits : new boolean[0];
+    }
+
+    public static void hit(int id) {
+        if (id < HITS.length) HITS[id] = true;
+    }
+}
+";
+
+/// How a test VM records coverage.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Recorder
+{
+    /// JaCoCo's agent, given as its `-javaagent` option.
+    Agent(String),
+    /// Classes instrumented by lvjb in `classes`, with `probes` ids.
+    Probes { classes: String, probes: usize },
+}
+
+/// Line coverage of source files: whether each line ran.
+pub type Lines = BTreeMap<PathBuf, BTreeMap<u32, bool>>;
+
+/// Source file and line of every built-in probe, by id.
+pub type ProbeLines = Vec<(PathBuf, u32)>;
+
+/// Coverage recording for one `lvjb test --coverage` run.
+pub enum Coverage
+{
+    Jacoco { recorder: Recorder, cli: PathBuf, exec: PathBuf, classes: Vec<PathBuf> },
+    Builtin { recorder: Recorder, probes: ProbeLines },
+}
+
+/// Recorder of the running `--coverage` run, for `test-worker` processes.
+static WORKER_ENV: Mutex<Option<String>> = Mutex::new(None);
+/// Probe hits reported back by `test-worker` processes.
+static FORKED_HITS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+#[inline(always)]
+pub fn  worker_env() -> Option<String>
+{
+    WORKER_ENV.lock().ok()?.clone()
+}
+
+/// Merges the probe hits of a `test-worker` process.
+pub fn  add_forked_hits(ids: Vec<u32>)
+{
+    if let Ok(mut hits) = FORKED_HITS.lock()
+    {
+        hits.extend(ids);
+    }
+}
+
+impl Recorder
+{
+    /// The recorder a `test-worker` process was started with, if any.
+    pub fn  from_env() -> Option<Self>
+    {
+        serde_json::from_str(&env::var(COVERAGE_ENV).ok()?).ok()
+    }
+
+    /// Adds the agent to the VM options, or puts the instrumented classes in
+    /// front of `paths.bin`.
+    pub fn  apply(&self, config: &mut Config)
+    {
+        match self
+        {
+            Recorder::Agent(option) => config.args.jvm.get_or_insert_with(Vec::new).push(option.clone()),
+            Recorder::Probes { classes, .. } => config.classpath.insert(0, classes.clone()),
+        }
+    }
+
+    /// Gives the probes a fresh array to record into.
+    pub fn  start(&self, jvm: &JavaVM) -> Result<(), Box<dyn std::error::Error>>
+    {
+        let Recorder::Probes { probes, .. } = self else { return Ok(()) };
+        let mut env = jvm.attach_current_thread()?;
+        env.with_local_frame(8, |env| -> Result<(), jni::errors::Error>
+        {
+            let hits = env.new_boolean_array(*probes as i32)?;
+            let key = env.new_string(HITS_PROPERTY)?;
+            let props = env.call_static_method("java/lang/System", "getProperties", "()Ljava/util/Properties;", &[])?.l()?;
+            env.call_method(&props, "put", "(Ljava/lang/Object;Ljava/lang/Object;)Ljava/lang/Object;", &[JValue::Object(&key), JValue::Object(&hits)])?;
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// Ids of the probes that ran. With the agent, its data is written out
+    /// to its `destfile` instead.
+    pub fn  finish(&self, jvm: &JavaVM) -> Result<Vec<u32>, Box<dyn std::error::Error>>
+    {
+        let mut env = jvm.attach_current_thread()?;
+        let result = env.with_local_frame(8, |env| -> Result<Vec<u32>, jni::errors::Error>
+        {
+            if let Recorder::Agent(_) = self
+            {
+                let agent = env.call_static_method("org/jacoco/agent/rt/RT", "getAgent", "()Lorg/jacoco/agent/rt/IAgent;", &[])?.l()?;
+                env.call_method(&agent, "dump", "(Z)V", &[JValue::Bool(1)])?;
+                return Ok(Vec::new());
+            }
+            let key = env.new_string(HITS_PROPERTY)?;
+            let props = env.call_static_method("java/lang/System", "getProperties", "()Ljava/util/Properties;", &[])?.l()?;
+            let hits = JBooleanArray::from(env.call_method(&props, "remove", "(Ljava/lang/Object;)Ljava/lang/Object;", &[JValue::Object(&key)])?.l()?);
+            if hits.is_null()
+            {
+                return Ok(Vec::new());
+            }
+            let mut buf = vec![0; env.get_array_length(&hits)? as usize];
+            env.get_boolean_array_region(&hits, 0, &mut buf)?;
+            Ok((0..buf.len() as u32).filter(|i| buf[*i as usize] != 0).collect())
+        });
+        result.map_err(|e| describe_exception(&mut env).unwrap_or_else(|_| e.to_string()).into())
+    }
+}
+
+/// Last (by name) jar in `paths.lib` whose file name `pick` accepts.
+fn  find_jar(config: &Config, pick: impl Fn(&str) -> bool) -> Option<PathBuf>
+{
+    let mut jars: Vec<PathBuf> = fs::read_dir(&config.paths.lib).ok()?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|x| x == "jar"))
+        .filter(|p| p.file_name().is_some_and(|n| pick(&n.to_string_lossy())))
+        .collect();
+    jars.sort();
+    jars.pop()
+}
+
+/// Source of class `name` (internal form) declared in `source_file`, in the
+/// `src/` layout: its package directory, or `src_nopkg` for the default package.
+pub fn  source_path(name: &str, source_file: &str, config: &Config) -> PathBuf
+{
+    let package = name.rsplit_once('/').map_or(config.paths.src_nopkg.as_str(), |(p, _)| p);
+    forge_sys_path(package, config, PathType::SRC).join(source_file)
+}
+
+/// Class files compiled from `paths.src`, tests and lvjb's own left out.
+fn  main_classes(config: &Config) -> Vec<PathBuf>
+{
+    let mut classes: Vec<PathBuf> = config.cache.outputs.iter()
+        .filter(|(src, _)| Path::new(src).starts_with(&config.paths.src))
+        .flat_map(|(_, outputs)| outputs.keys().map(PathBuf::from))
+        .collect();
+    classes.sort();
+    classes
+}
+
+/// Instruments every main class into `<COVERAGE_DIR>/classes`.
+fn  instrument_all(config: &Config) -> Result<(Recorder, ProbeLines), Box<dyn std::error::Error>>
+{
+    let dir = PathBuf::from(COVERAGE_DIR).join("classes");
+    if dir.exists()
+    {
+        fs::remove_dir_all(&dir)?;
+    }
+    let mut probes = ProbeLines::new();
+    for class in main_classes(config)
+    {
+        let buf = fs::read(&class)?;
+        let Some(info) = parse_class(&buf) else { continue };
+        let Some(source) = info.source_file.as_deref().map(|s| source_path(&info.name, s, config)) else { continue };
+        let Some((out, lines)) = instrument_class(&buf, probes.len() as u32) else { continue };
+        probes.extend(lines.into_iter().map(|l| (source.clone(), l as u32)));
+        let target = dir.join(class.strip_prefix(&config.paths.bin).unwrap_or(&class));
+        if let Some(parent) = target.parent()
+        {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(target, out)?;
+    }
+    let recorder = Recorder::Probes { classes: dir.to_string_lossy().into_owned(), probes: probes.len() };
+    Ok((recorder, probes))
+}
+
+impl Coverage
+{
+    /// Sets up coverage for a test run with JaCoCo when its agent and CLI jars
+    /// are in `paths.lib`, and the built-in probes otherwise. `run_config`,
+    /// the configuration the tests run with, is set up for it.
+    pub fn  prepare(config: &Config, run_config: &mut Config) -> Result<Self, Box<dyn std::error::Error>>
+    {
+        fs::create_dir_all(COVERAGE_DIR)?;
+        // Maven's `org.jacoco.agent` jar only wraps the agent, the `runtime` one is it.
+        let agent = find_jar(config, |n| n.starts_with("jacocoagent") || (n.starts_with("org.jacoco.agent") && n.contains("runtime")));
+        let cli = find_jar(config, |n| n.starts_with("jacococli") || n.starts_with("org.jacoco.cli"));
+        let coverage = match (agent, cli)
+        {
+            (Some(_), Some(_)) if jvm_started() =>
+            {
+                eprintln!("{ORANGE}[COVERAGE]{RESET} The VM is already running without the JaCoCo agent, using built-in coverage");
+                let (recorder, probes) = instrument_all(config)?;
+                Coverage::Builtin { recorder, probes }
+            }
+            (Some(agent), Some(cli)) =>
+            {
+                let exec = PathBuf::from(COVERAGE_DIR).join("jacoco.exec");
+                if exec.exists()
+                {
+                    fs::remove_file(&exec)?;
+                }
+                let option = format!("-javaagent:{}=destfile={}", agent.display(), exec.display());
+                eprintln!("{GREEN}[COVERAGE]{RESET} Recording with {}", agent.display());
+                Coverage::Jacoco { recorder: Recorder::Agent(option), cli, exec, classes: main_classes(config) }
+            }
+            (agent, _) =>
+            {
+                if let Some(agent) = agent
+                {
+                    eprintln!("{ORANGE}[COVERAGE]{RESET} Found {} but no jacococli jar to read its data, using built-in coverage", agent.display());
+                }
+                let (recorder, probes) = instrument_all(config)?;
+                Coverage::Builtin { recorder, probes }
+            }
+        };
+        let recorder = coverage.recorder();
+        recorder.apply(run_config);
+        *WORKER_ENV.lock().map_err(|e| e.to_string())? = Some(serde_json::to_string(recorder)?);
+        FORKED_HITS.lock().map_err(|e| e.to_string())?.clear();
+        Ok(coverage)
+    }
+
+    #[inline(always)]
+    pub fn  recorder(&self) -> &Recorder
+    {
+        match self
+        {
+            Coverage::Jacoco { recorder, .. } | Coverage::Builtin { recorder, .. } => recorder,
+        }
+    }
+
+    /// Collects what the run covered.
+    pub fn  finish(&self, jvm: &JavaVM, config: &Config) -> Result<Lines, Box<dyn std::error::Error>>
+    {
+        let hits = self.recorder().finish(jvm);
+        *WORKER_ENV.lock().map_err(|e| e.to_string())? = None;
+        let hits = hits?;
+        match self
+        {
+            Coverage::Builtin { probes, .. } =>
+            {
+                let mut lines = Lines::new();
+                for (source, line) in probes
+                {
+                    lines.entry(source.clone()).or_default().insert(*line, false);
+                }
+                let forked = std::mem::take(&mut *FORKED_HITS.lock().map_err(|e| e.to_string())?);
+                for id in hits.into_iter().chain(forked)
+                {
+                    if let Some((source, line)) = probes.get(id as usize)
+                        && let Some(x) = lines.get_mut(source).and_then(|l| l.get_mut(line))
+                    {
+                        *x = true;
+                    }
+                }
+                Ok(lines)
+            }
+            Coverage::Jacoco { cli, exec, classes, .. } =>
+            {
+                let xml = PathBuf::from(COVERAGE_DIR).join("jacoco.xml");
+                let mut cmd = Command::new("java");
+                cmd.arg("-jar").arg(cli).arg("report").arg(exec);
+                for class in classes
+                {
+                    cmd.arg("--classfiles").arg(class);
+                }
+                cmd.arg("--sourcefiles").arg(&config.paths.src).arg("--xml").arg(&xml);
+                let output = cmd.output()?;
+                if !output.status.success()
+                {
+                    return Err(format!("{RED}[COVERAGE]{RESET} jacococli report failed:\n{}", String::from_utf8_lossy(&output.stderr)).into());
+                }
+                Ok(jacoco_lines(&fs::read_to_string(&xml)?, config))
+            }
+        }
+    }
+}
+
+/// Value of `key="..."` inside one XML tag.
+#[inline(always)]
+fn  attr<'a>(tag: &'a str, key: &str) -> Option<&'a str>
+{
+    let start = tag.find(&format!(" {key}=\""))? + key.len() + 3;
+    tag[start..].split('"').next()
+}
+
+/// Line coverage from a JaCoCo XML report. A line counts as run when any of
+/// its instructions was covered.
+pub fn  jacoco_lines(xml: &str, config: &Config) -> Lines
+{
+    let mut lines = Lines::new();
+    let (mut package, mut source): (Option<&str>, Option<PathBuf>) = (None, None);
+    for tag in xml.split('<').filter_map(|t| t.split('>').next())
+    {
+        if tag.starts_with("package ")
+        {
+            package = attr(tag, "name");
+        }
+        else if tag.starts_with("sourcefile ")
+        {
+            source = attr(tag, "name").map(|file| match package
+            {
+                Some(p) if !p.is_empty() => source_path(&format!("{p}/{file}"), file, config),
+                _ => source_path(file, file, config),
+            });
+        }
+        else if tag == "/sourcefile"
+        {
+            source = None;
+        }
+        else if tag.starts_with("line ")
+            && let Some(src) = &source
+            && let Some(nr) = attr(tag, "nr").and_then(|n| n.parse().ok())
+        {
+            let covered = attr(tag, "ci").and_then(|n| n.parse::<u32>().ok()).unwrap_or(0) > 0;
+            lines.entry(src.clone()).or_default().insert(nr, covered);
+        }
+    }
+    lines
+}
+
+/// LCOV tracefile of `lines`.
+pub fn  lcov(lines: &Lines) -> String
+{
+    let mut out = String::new();
+    for (source, hits) in lines
+    {
+        out.push_str(&format!("TN:\nSF:{}\n", source.display()));
+        for (line, hit) in hits
+        {
+            out.push_str(&format!("DA:{line},{}\n", *hit as u8));
+        }
+        let covered = hits.values().filter(|h| **h).count();
+        out.push_str(&format!("LF:{}\nLH:{covered}\nend_of_record\n", hits.len()));
+    }
+    out
+}
+
+#[inline(always)]
+fn  percent(covered: usize, total: usize) -> String
+{
+    match total
+    {
+        0 => "-".to_string(),
+        _ => format!("{:.1}%", covered as f64 * 100.0 / total as f64),
+    }
+}
+
+/// Covered and total lines per package, then the totals, on stderr.
+pub fn  print_coverage(lines: &Lines, config: &Config)
+{
+    let mut packages: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    for (source, hits) in lines
+    {
+        let package = source_package(source, config)
+            .map(|p| p.to_string_lossy().replace(['/', '\\'], "."))
+            .filter(|p| !p.is_empty())
+            .unwrap_or_else(|| "(default)".to_string());
+        let entry = packages.entry(package).or_default();
+        entry.0 += hits.values().filter(|h| **h).count();
+        entry.1 += hits.len();
+    }
+    let width = packages.keys().map(|p| p.len()).max().unwrap_or(0).max("total".len());
+    let row = |name: &str, covered: usize, total: usize|
+        eprintln!("  {name:<width$}  {covered:>7}  {total:>5}  {:>6}", percent(covered, total));
+    eprintln!("{GREEN}[COVERAGE]{RESET}");
+    eprintln!("  {:<width$}  {:>7}  {:>5}  {:>6}", "package", "covered", "lines", "%");
+    for (package, (covered, total)) in &packages
+    {
+        row(package, *covered, *total);
+    }
+    let (covered, total) = packages.values().fold((0, 0), |a, b| (a.0 + b.0, a.1 + b.1));
+    row("total", covered, total);
+}
+
+/// Writes `<COVERAGE_DIR>/lcov.info` and prints the summary.
+pub fn  report_coverage(lines: &Lines, config: &Config) -> Result<(), Box<dyn std::error::Error>>
+{
+    let path = PathBuf::from(COVERAGE_DIR).join("lcov.info");
+    fs::write(&path, lcov(lines))?;
+    print_coverage(lines, config);
+    eprintln!("{GREEN}[REPORT]{RESET} LCOV written to {}", path.display());
+    Ok(())
+}
@@ -0,0 +1,368 @@
+use crate::config::*;
+use crate::jvm::*;
+use crate::compiler::*;
+use crate::testrunner::{exit_guard, EXIT_GUARD};
+use crate::spawn::{GREEN, ORANGE, RED, RESET};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::io::{stdout, stderr, Read, Write, BufRead, BufReader};
+use std::os::fd::{AsRawFd, RawFd};
+use jni::JavaVM;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+pub const SOCKET_FILE: &str = ".lvjb.sock";
+
+/// Commands a running daemon executes on behalf of the client.
+pub const FORWARDED: [&str; 3] = ["build", "run", "test"];
+
+/// Where the daemon compiles `lvjb.ExitGuard` for `run`, apart from `paths.bin`.
+const GUARD_DIR: &str = ".lvjb/guard";
+
+static IN_DAEMON: AtomicBool = AtomicBool::new(false);
+
+/// True while the current process is serving daemon requests.
+#[inline(always)]
+pub fn  in_daemon() -> bool
+{
+    IN_DAEMON.load(Ordering::Relaxed)
+}
+
+fn  send_fds(sock: &UnixStream, payload: &[u8], fds: &[RawFd]) -> std::io::Result<()>
+{
+    let fd_bytes = std::mem::size_of_val(fds);
+    let space = unsafe { libc::CMSG_SPACE(fd_bytes as u32) } as usize;
+    let mut cbuf = vec![0u8; space];
+    let mut iov = libc::iovec
+    {
+        iov_base: payload.as_ptr() as *mut libc::c_void,
+        iov_len: payload.len(),
+    };
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = cbuf.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = space as _;
+    unsafe
+    {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(fd_bytes as u32) as _;
+        std::ptr::copy_nonoverlapping(fds.as_ptr() as *const u8, libc::CMSG_DATA(cmsg), fd_bytes);
+        if libc::sendmsg(sock.as_raw_fd(), &msg, 0) < 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn  recv_fds(sock: &UnixStream, payload: &mut [u8]) -> std::io::Result<(usize, Vec<RawFd>)>
+{
+    let space = unsafe { libc::CMSG_SPACE((3 * std::mem::size_of::<RawFd>()) as u32) } as usize;
+    let mut cbuf = vec![0u8; space];
+    let mut iov = libc::iovec
+    {
+        iov_base: payload.as_mut_ptr() as *mut libc::c_void,
+        iov_len: payload.len(),
+    };
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = cbuf.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = space as _;
+    let mut fds = Vec::new();
+    unsafe
+    {
+        let n = libc::recvmsg(sock.as_raw_fd(), &mut msg, 0);
+        if n < 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null()
+        {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS
+            {
+                let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                let count = ((*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / std::mem::size_of::<RawFd>();
+                for i in 0..count
+                {
+                    fds.push(std::ptr::read_unaligned(data.add(i)));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+        Ok((n as usize, fds))
+    }
+}
+
+/// Points fds 0-2 at the client's stdio for the duration of a request and
+/// restores the daemon's own on drop.
+struct Redirect
+{
+    saved: [RawFd; 3],
+}
+
+impl Redirect
+{
+    fn  new(fds: &[RawFd]) -> Self
+    {
+        let mut saved = [-1; 3];
+        for (target, fd) in fds.iter().take(3).enumerate()
+        {
+            unsafe
+            {
+                saved[target] = libc::dup(target as RawFd);
+                libc::dup2(*fd, target as RawFd);
+            }
+        }
+        Redirect { saved }
+    }
+}
+
+impl Drop for Redirect
+{
+    fn  drop(&mut self)
+    {
+        let _ = stdout().flush();
+        let _ = stderr().flush();
+        for (target, fd) in self.saved.iter().enumerate()
+        {
+            if *fd >= 0
+            {
+                unsafe
+                {
+                    libc::dup2(*fd, target as RawFd);
+                    libc::close(*fd);
+                }
+            }
+        }
+    }
+}
+
+/// Sends `args` to a running daemon. Returns `None` when no daemon is
+/// listening, so the caller falls back to running the command itself.
+pub fn  forward(args: &[String]) -> Option<i32>
+{
+    let sock = UnixStream::connect(SOCKET_FILE).ok()?;
+    let cwd = env::current_dir().and_then(fs::canonicalize).ok()?;
+    let payload = format!("exec\0{}\0{}", cwd.display(), args.join("\0"));
+    send_fds(&sock, payload.as_bytes(), &[0, 1, 2]).ok()?;
+    let _ = sock.shutdown(std::net::Shutdown::Write);
+    let mut reply = String::new();
+    if BufReader::new(&sock).read_line(&mut reply).is_err()
+    {
+        eprintln!("{RED}[DAEMON]{RESET} Lost connection to daemon");
+        return Some(1);
+    }
+    if let Some(dir) = reply.trim().strip_prefix("refused ")
+    {
+        eprintln!("{ORANGE}[DAEMON]{RESET} The daemon on {SOCKET_FILE} serves {dir}, running here without it");
+        return None;
+    }
+    match reply.trim().strip_prefix("exit ").and_then(|x| x.parse::<i32>().ok())
+    {
+        Some(code) => Some(code),
+        None =>
+        {
+            eprintln!("{RED}[DAEMON]{RESET} Daemon exited while running the command");
+            Some(1)
+        }
+    }
+}
+
+fn  request(kind: &str) -> Result<String, Box<dyn std::error::Error>>
+{
+    let mut sock = UnixStream::connect(SOCKET_FILE)?;
+    sock.write_all(kind.as_bytes())?;
+    sock.shutdown(std::net::Shutdown::Write)?;
+    let mut reply = String::new();
+    sock.read_to_string(&mut reply)?;
+    Ok(reply)
+}
+
+/// Connects to the daemon, cleaning up a stale socket left behind by a crash.
+#[inline(always)]
+fn  running() -> bool
+{
+    if UnixStream::connect(SOCKET_FILE).is_ok()
+    {
+        return true;
+    }
+    let _ = fs::remove_file(SOCKET_FILE);
+    false
+}
+
+pub fn  status() -> Result<(), Box<dyn std::error::Error>>
+{
+    if !running()
+    {
+        eprintln!("{ORANGE}[DAEMON]{RESET} Not running");
+        return Ok(());
+    }
+    eprintln!("{GREEN}[DAEMON]{RESET} {}", request("status")?.trim());
+    Ok(())
+}
+
+pub fn  stop() -> Result<(), Box<dyn std::error::Error>>
+{
+    if !running()
+    {
+        return Err(format!("{RED}[DAEMON]{RESET} Not running").into());
+    }
+    request("stop")?;
+    eprintln!("{GREEN}[DAEMON]{RESET} Stopped");
+    Ok(())
+}
+
+/// Starts `lvjb daemon --foreground` detached from the terminal.
+pub fn  start() -> Result<(), Box<dyn std::error::Error>>
+{
+    if running()
+    {
+        return Err(format!("{RED}[DAEMON]{RESET} Already running").into());
+    }
+    let child = Command::new(std::env::current_exe()?)
+        .arg("daemon")
+        .arg("--foreground")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()?;
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while Instant::now() < deadline
+    {
+        if UnixStream::connect(SOCKET_FILE).is_ok()
+        {
+            eprintln!("{GREEN}[DAEMON]{RESET} Started (pid {})", child.id());
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    Err(format!("{RED}[DAEMON]{RESET} Daemon did not come up").into())
+}
+
+/// Installs `lvjb.ExitGuard` (or removes it) around a program `run` inside
+/// the daemon, so its `System.exit` ends the program, not the daemon. The
+/// guard is compiled on its own into `GUARD_DIR` the first time. False when
+/// this JDK can't intercept exits.
+pub fn  guard_exit(jvm: &JavaVM, config: &Config, install: bool) -> Result<bool, Box<dyn std::error::Error>>
+{
+    let mut guard = config.clone();
+    guard.paths.bin = GUARD_DIR.to_string();
+    guard.classpath = vec![GUARD_DIR.to_string()];
+    guard.args.compilation = None;
+    let source = Path::new(GUARD_DIR).join("lvjb").join("ExitGuard.java");
+    if install
+        && (fs::read_to_string(&source).ok().as_deref() != Some(EXIT_GUARD) || !source.with_extension("class").exists())
+    {
+        fs::create_dir_all(Path::new(GUARD_DIR).join("lvjb"))?;
+        fs::write(&source, EXIT_GUARD)?;
+        let (ok, diagnostics) = compile_in_process(&[source], &guard)?;
+        if !ok
+        {
+            let errors: Vec<String> = diagnostics.iter().map(ToString::to_string).collect();
+            return Err(format!("{RED}[DAEMON]{RESET} Couldn't compile lvjb.ExitGuard:\n{}", errors.join("\n")).into());
+        }
+    }
+    exit_guard(jvm, &guard, install)
+}
+
+/// Status a guarded program passed to `System.exit`, from the exception
+/// `guard_exit`'s guard turned it into.
+#[inline(always)]
+pub fn  exit_status(exception: &str) -> Option<i32>
+{
+    exception.strip_prefix("lvjb.ExitGuard$Exit: System.exit(")?.strip_suffix(") called")?.parse().ok()
+}
+
+/// Binds the socket readable and writable by the owner only: whoever can
+/// connect runs commands as this user.
+fn  bind_private(path: &str) -> std::io::Result<UnixListener>
+{
+    // SAFETY: umask only touches this process's file creation mask, restored
+    // right after; nothing else creates files while the daemon starts.
+    let saved = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(path);
+    unsafe { libc::umask(saved); }
+    listener
+}
+
+/// Warms up the JVM (and javax.tools, when it is the configured compiler),
+/// then serves requests one at a time, running each through `handler` with
+/// the client's stdio. Requests from another directory are refused.
+pub fn  serve(config: &Config, handler: fn(Vec<String>) -> Result<(), i32>) -> Result<(), Box<dyn std::error::Error>>
+{
+    let _ = fs::remove_file(SOCKET_FILE);
+    let home: PathBuf = fs::canonicalize(env::current_dir()?)?;
+    shared_jvm(config)?;
+    if config.compiler == IN_PROCESS_COMPILER
+    {
+        let _ = compile_in_process(&[], config);
+    }
+    else
+    {
+        eprintln!("{ORANGE}[DAEMON]{RESET} compiler = \"{}\": builds still start it, set compiler = \"{IN_PROCESS_COMPILER}\" to compile in the warm VM", config.compiler);
+    }
+    let listener = bind_private(SOCKET_FILE)?;
+    IN_DAEMON.store(true, Ordering::Relaxed);
+    let started = Instant::now();
+    let mut served: u64 = 0;
+    eprintln!("{GREEN}[DAEMON]{RESET} Listening on {SOCKET_FILE}");
+
+    for conn in listener.incoming()
+    {
+        let Ok(mut sock) = conn else { continue };
+        let mut buf = vec![0u8; 64 * 1024];
+        let Ok((n, fds)) = recv_fds(&sock, &mut buf) else { continue };
+        let payload = String::from_utf8_lossy(&buf[..n]).to_string();
+        let mut parts = payload.split('\0');
+        match parts.next()
+        {
+            Some("exec") if fds.len() == 3 && parts.clone().next() != Some(&*home.to_string_lossy()) =>
+            {
+                let _ = writeln!(sock, "refused {}", home.display());
+            }
+            Some("exec") if fds.len() == 3 =>
+            {
+                let args: Vec<String> = parts.skip(1).map(str::to_string).collect();
+                let code = {
+                    let _redirect = Redirect::new(&fds);
+                    let code = match handler(args)
+                    {
+                        Ok(_) => 0,
+                        Err(code) => code,
+                    };
+                    flush_java_streams();
+                    code
+                };
+                served += 1;
+                let _ = writeln!(sock, "exit {code}");
+            }
+            Some("status") =>
+            {
+                let _ = writeln!(sock, "Running (pid {}), up {}s, {} request(s) served",
+                    std::process::id(), started.elapsed().as_secs(), served);
+            }
+            Some("stop") =>
+            {
+                let _ = writeln!(sock, "ok");
+                break;
+            }
+            _ => (),
+        }
+        for fd in fds
+        {
+            unsafe { libc::close(fd); }
+        }
+    }
+    let _ = fs::remove_file(SOCKET_FILE);
+    Ok(())
+}
@@ -0,0 +1,181 @@
+use crate::spawn::{GREEN, ORANGE, RED, RESET};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+pub const MESSAGE_FORMAT_JSON: &str = "json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity
+{
+    Error,
+    Warning,
+    Note,
+}
+
+impl Severity
+{
+    #[inline(always)]
+    pub fn  as_str(&self) -> &'static str
+    {
+        match self
+        {
+            Severity::Error     => "error",
+            Severity::Warning   => "warning",
+            Severity::Note      => "note",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic
+{
+    pub file:       Option<String>,
+    pub line:       Option<u64>,
+    pub column:     Option<u64>,
+    pub severity:   Severity,
+    pub message:    String,
+}
+
+impl fmt::Display for Diagnostic
+{
+    fn  fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        let color = match self.severity
+        {
+            Severity::Error => RED,
+            _ => ORANGE,
+        };
+        if let Some(file) = &self.file
+        {
+            write!(f, "{file}:")?;
+            if let Some(line) = self.line
+            {
+                write!(f, "{line}:")?;
+            }
+            if let Some(col) = self.column
+            {
+                write!(f, "{col}:")?;
+            }
+            write!(f, " ")?;
+        }
+        write!(f, "{color}{}{RESET}: {}", self.severity.as_str(), self.message)
+    }
+}
+
+#[inline(always)]
+fn  parse_header(line: &str) -> Option<(Option<String>, Option<u64>, Severity, String)>
+{
+    for (tag, severity) in [("error: ", Severity::Error), ("warning: ", Severity::Warning), ("note: ", Severity::Note)]
+    {
+        if let Some(msg) = line.strip_prefix(tag)
+        {
+            return Some((None, None, severity, msg.to_string()));
+        }
+        let needle = format!(": {tag}");
+        if let Some(pos) = line.find(&needle)
+        {
+            let (location, msg) = (&line[..pos], &line[pos + needle.len()..]);
+            let (file, lineno) = location.rsplit_once(':')?;
+            let lineno = lineno.parse::<u64>().ok()?;
+            return Some((Some(file.to_string()), Some(lineno), severity, msg.to_string()));
+        }
+    }
+    line.strip_prefix("Note: ").map(|msg| (None, None, Severity::Note, msg.to_string()))
+}
+
+#[inline(always)]
+fn  is_count_line(line: &str) -> bool
+{
+    let mut parts = line.split_whitespace();
+    parts.next().is_some_and(|n| n.parse::<u64>().is_ok())
+        && matches!(parts.next(), Some("error" | "errors" | "warning" | "warnings"))
+        && parts.next().is_none()
+}
+
+/// Parses javac's human-readable output: a `file:line: severity: message`
+/// header, the source line with a caret marking the column, and indented
+/// detail lines (`symbol:`, `location:`) on either side of it.
+pub fn  parse_javac(output: &str) -> Vec<Diagnostic>
+{
+    let mut out: Vec<Diagnostic> = Vec::new();
+    let mut body: Vec<&str> = Vec::new();
+    let flush = |out: &mut Vec<Diagnostic>, body: &mut Vec<&str>|
+    {
+        let Some(d) = out.last_mut() else { return };
+        // The source line echo and its caret are not part of the message.
+        let caret = body.iter().position(|l| l.trim() == "^");
+        if let Some(i) = caret
+        {
+            d.column = body[i].find('^').map(|c| c as u64 + 1);
+        }
+        let details = body.iter().enumerate()
+            .filter(|(j, _)| caret.is_none_or(|i| *j != i && *j + 1 != i))
+            .map(|(_, l)| l);
+        for l in details
+        {
+            d.message.push('\n');
+            d.message.push_str(l);
+        }
+        body.clear();
+    };
+    for line in output.lines()
+    {
+        if let Some((file, lineno, severity, message)) = parse_header(line)
+        {
+            flush(&mut out, &mut body);
+            out.push(Diagnostic { file, line: lineno, column: None, severity, message });
+        }
+        else if is_count_line(line)
+        {
+            flush(&mut out, &mut body);
+        }
+        else if !out.is_empty()
+        {
+            body.push(line);
+        }
+    }
+    flush(&mut out, &mut body);
+    out
+}
+
+/// Prints error/warning totals grouped by file.
+pub fn  print_summary(diagnostics: &[Diagnostic])
+{
+    if diagnostics.is_empty()
+    {
+        return;
+    }
+    let mut by_file: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    for d in diagnostics
+    {
+        let entry = by_file.entry(d.file.as_deref().unwrap_or("<no file>")).or_default();
+        match d.severity
+        {
+            Severity::Error => entry.0 += 1,
+            Severity::Warning => entry.1 += 1,
+            Severity::Note => (),
+        }
+    }
+    let errors: usize = by_file.values().map(|x| x.0).sum();
+    let warnings: usize = by_file.values().map(|x| x.1).sum();
+    let color = if errors > 0 { RED } else if warnings > 0 { ORANGE } else { GREEN };
+    eprintln!("{color}[DIAGNOSTICS]{RESET} {errors} error(s), {warnings} warning(s)");
+    for (file, (e, w)) in by_file.iter().filter(|(_, (e, w))| e + w > 0)
+    {
+        eprintln!("  {file}: {e} error(s), {w} warning(s)");
+    }
+}
+
+/// One JSON object per diagnostic on stdout, for editors and CI annotators.
+pub fn  print_json(diagnostics: &[Diagnostic])
+{
+    for d in diagnostics
+    {
+        if let Ok(line) = serde_json::to_string(d)
+        {
+            println!("{line}");
+        }
+    }
+}
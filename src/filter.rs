@@ -0,0 +1,251 @@
+use crate::config::*;
+use crate::testrunner::test_class_name;
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+
+/// Which tests `lvjb test` runs. Names are `Class.method`, or `Class` for
+/// classes run through `main`. Patterns are globs (`*`, `?`) or substrings,
+/// or whole names with `exact`.
+#[derive(Debug, Clone, Default)]
+pub struct TestFilter
+{
+    pub patterns:       Vec<String>,
+    pub skip:           Vec<String>,
+    pub exact:          bool,
+    pub include_tags:   Vec<String>,
+}
+
+/// Pattern states reachable after reading `text`; state `i` means
+/// `pattern[..i]` has been matched.
+fn  glob_states(pattern: &[char], text: &str) -> Vec<bool>
+{
+    let close = |states: &mut Vec<bool>|
+    {
+        for i in 0..pattern.len()
+        {
+            if states[i] && pattern[i] == '*'
+            {
+                states[i + 1] = true;
+            }
+        }
+    };
+    let mut states = vec![false; pattern.len() + 1];
+    states[0] = true;
+    close(&mut states);
+    for c in text.chars()
+    {
+        let mut next = vec![false; pattern.len() + 1];
+        for i in 0..pattern.len()
+        {
+            if !states[i]
+            {
+                continue;
+            }
+            match pattern[i]
+            {
+                '*' => next[i] = true,
+                '?' => next[i + 1] = true,
+                p if p == c => next[i + 1] = true,
+                _ => (),
+            }
+        }
+        close(&mut next);
+        states = next;
+    }
+    states
+}
+
+/// Substrings are globs with a `*` on each side.
+#[inline(always)]
+fn  as_glob(pattern: &str) -> Vec<char>
+{
+    match pattern.contains(['*', '?'])
+    {
+        true => pattern.chars().collect(),
+        false => format!("*{pattern}*").chars().collect(),
+    }
+}
+
+impl TestFilter
+{
+    #[inline(always)]
+    fn  matches_name(&self, pattern: &str, name: &str) -> bool
+    {
+        if self.exact
+        {
+            return pattern == name;
+        }
+        let glob = as_glob(pattern);
+        glob_states(&glob, name)[glob.len()]
+    }
+
+    /// Whether `pattern` can match `class` or one of the methods declared in
+    /// `source`. Method names appear verbatim in the source, so the literal
+    /// parts of the pattern left over after `Class.` must be found in it.
+    fn  may_match_class(&self, pattern: &str, class: &str, source: &str) -> bool
+    {
+        if self.exact
+        {
+            return pattern == class || pattern.strip_prefix(class)
+                .and_then(|m| m.strip_prefix('.'))
+                .is_some_and(|m| source.contains(m));
+        }
+        let glob = as_glob(pattern);
+        if glob_states(&glob, class)[glob.len()]
+        {
+            return true;
+        }
+        glob_states(&glob, &format!("{class}.")).iter()
+            .enumerate()
+            .filter(|(_, reached)| **reached)
+            .any(|(i, _)| glob[i..]
+                .split(|c| *c == '*' || *c == '?')
+                .all(|lit| source.contains(&lit.iter().collect::<String>())))
+    }
+
+    /// Whether the test `class.method` (or the `main`-style `class`) with
+    /// `tags` is selected. An exact class name selects all its methods.
+    pub fn  matches(&self, class: &str, method: Option<&str>, tags: &[String]) -> bool
+    {
+        let name = match method
+        {
+            Some(m) => format!("{class}.{m}"),
+            None => class.to_string(),
+        };
+        let hit = |p: &String| self.matches_name(p, &name) || (self.exact && p == class);
+        (self.patterns.is_empty() || self.patterns.iter().any(hit))
+            && !self.skip.iter().any(hit)
+            && (self.include_tags.is_empty() || tags.iter().any(|t| self.include_tags.contains(t)))
+    }
+
+    /// Whether `class`, compiled from `source`, can hold a selected test.
+    pub fn  may_select(&self, class: &str, source: &str) -> bool
+    {
+        // A skip pattern found in the class name itself skips all its tests.
+        let skipped = self.skip.iter().any(|p| match self.exact
+        {
+            true => p == class,
+            false => !p.contains(['*', '?']) && class.contains(p.as_str()),
+        });
+        !skipped && (self.patterns.is_empty() || self.patterns.iter().any(|p| self.may_match_class(p, class, source)))
+    }
+
+    #[inline(always)]
+    pub fn  selects_all(&self) -> bool
+    {
+        self.patterns.is_empty() && self.skip.is_empty()
+    }
+}
+
+/// The test sources to compile for `filter`: those that may hold a selected
+/// test, the test sources they depend on, and lvjb's support sources. If a
+/// selected source was never compiled its dependencies are unknown, and
+/// everything is kept.
+pub fn  select_sources(files: &[PathBuf], filter: &TestFilter, config: &Config) -> Vec<PathBuf>
+{
+    if filter.selects_all()
+    {
+        return files.to_vec();
+    }
+    let key = |f: &PathBuf| f.to_string_lossy().into_owned();
+    let known: HashSet<String> = files.iter().map(key).collect();
+    let mut picked: Vec<String> = files.iter()
+        .filter(|f| test_class_name(f, config).is_none_or(|c| filter.may_select(&c, &fs::read_to_string(f).unwrap_or_default())))
+        .map(key)
+        .collect();
+    if picked.iter().any(|f| !config.cache.outputs.contains_key(f))
+    {
+        return files.to_vec();
+    }
+    let mut selected: HashSet<String> = picked.iter().cloned().collect();
+    while let Some(file) = picked.pop()
+    {
+        for dep in config.cache.deps.get(&file).into_iter().flatten()
+        {
+            if known.contains(dep) && selected.insert(dep.clone())
+            {
+                picked.push(dep.clone());
+            }
+        }
+    }
+    files.iter().filter(|f| selected.contains(&key(f))).cloned().collect()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[inline(always)]
+    fn  glob(pattern: &str, text: &str) -> bool
+    {
+        let glob: Vec<char> = pattern.chars().collect();
+        glob_states(&glob, text)[glob.len()]
+    }
+
+    fn  filter(patterns: &[&str], skip: &[&str], exact: bool) -> TestFilter
+    {
+        TestFilter
+        {
+            patterns:       patterns.iter().map(|x| x.to_string()).collect(),
+            skip:           skip.iter().map(|x| x.to_string()).collect(),
+            exact,
+            include_tags:   Vec::new(),
+        }
+    }
+
+    #[test]
+    fn  glob_wildcards()
+    {
+        assert!(glob("*", ""));
+        assert!(glob("a*b", "ab"));
+        assert!(glob("a*b", "axxb"));
+        assert!(glob("a?c", "abc"));
+        assert!(glob("**.t*", "com.x.FooTest.test"));
+        assert!(glob("*Test.*", "FooTest.a"));
+        assert!(!glob("a?c", "ac"));
+        assert!(!glob("a*b", "abx"));
+        assert!(!glob("", "a"));
+    }
+
+    #[test]
+    fn  substrings_and_exact_names()
+    {
+        let f = filter(&["Foo"], &[], false);
+        assert!(f.matches("com.FooTest", Some("a"), &[]));
+        assert!(!f.matches("com.BarTest", Some("a"), &[]));
+        let f = filter(&["com.FooTest"], &[], true);
+        assert!(f.matches("com.FooTest", Some("a"), &[]));
+        assert!(f.matches("com.FooTest", None, &[]));
+        assert!(!f.matches("com.FooTest2", Some("a"), &[]));
+        let f = filter(&["com.FooTest.a"], &[], true);
+        assert!(f.matches("com.FooTest", Some("a"), &[]));
+        assert!(!f.matches("com.FooTest", Some("ab"), &[]));
+    }
+
+    #[test]
+    fn  skip_and_tags()
+    {
+        let f = filter(&["*Test.*"], &["slow"], false);
+        assert!(f.matches("FooTest", Some("fast"), &[]));
+        assert!(!f.matches("FooTest", Some("slowOne"), &[]));
+        let f = TestFilter { include_tags: vec!["db".into()], ..filter(&[], &[], false) };
+        assert!(f.matches("FooTest", Some("a"), &["db".into()]));
+        assert!(!f.matches("FooTest", Some("a"), &[]));
+    }
+
+    #[test]
+    fn  may_select_looks_for_method_names_in_the_source()
+    {
+        let source = "class FooTest { @Test void parsesInput() {} }";
+        assert!(filter(&["FooTest.parses*"], &[], false).may_select("FooTest", source));
+        assert!(filter(&["*.parsesInput"], &[], false).may_select("FooTest", source));
+        assert!(filter(&["Foo"], &[], false).may_select("FooTest", ""));
+        assert!(!filter(&["FooTest.writes*"], &[], false).may_select("FooTest", source));
+        assert!(!filter(&["BarTest.*"], &[], false).may_select("FooTest", source));
+        assert!(!filter(&[], &["Foo"], false).may_select("FooTest", source));
+        assert!(filter(&["FooTest.parsesInput"], &[], true).may_select("FooTest", source));
+        assert!(!filter(&["FooTest.other"], &[], true).may_select("FooTest", source));
+    }
+}
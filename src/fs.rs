@@ -1,5 +1,5 @@
 use crate::config::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::fs;
 
 pub enum PathType
@@ -30,6 +30,26 @@ pub fn  forge_sys_path(path: &str, config: &Config, ptype: PathType) -> PathBuf
     out
 }
 
+/// Where lvjb generates the Java sources its test runner needs (package
+/// `lvjb`), kept out of the project's own trees.
+pub const SUPPORT_DIR: &str = ".lvjb/support";
+
+/// Package directory a source file compiles into, following the src/, default/
+/// and test/ layout (e.g. `src/com/x/Foo.java` -> `com/x`).
+#[inline(always)]
+pub fn  source_package(p: &Path, config: &Config) -> Option<PathBuf>
+{
+    if p.starts_with(forge_sys_path(&config.paths.src_nopkg, config, PathType::SRC))
+    {
+        return Some(PathBuf::new());
+    }
+    let relative = p.strip_prefix(&config.paths.src)
+        .or_else(|_| p.strip_prefix(&config.paths.test))
+        .or_else(|_| p.strip_prefix(SUPPORT_DIR))
+        .ok()?;
+    relative.parent().map(Path::to_path_buf)
+}
+
 #[inline(always)]
 pub fn  class_to_path(s: &str) -> String
 {
@@ -50,11 +70,9 @@ pub fn fetch_files_under(p: &PathBuf, src_ext: &String) -> Vec<PathBuf>
                 results.extend(fetch_files_under(&path, src_ext));
             }
             if let Some(name) = entry.file_name().to_str()
+                && name.ends_with(src_ext)
             {
-                if name.ends_with(src_ext)
-                {
-                    results.push(path);
-                }
+                results.push(path);
             }
         }
     }
@@ -74,11 +92,9 @@ pub fn  expand_classpath(paths: &[String]) -> String
                 for entry in read_dir.flatten()
                 {
                     if let Some(ext) = entry.path().extension()
+                        && ext == "jar"
                     {
-                        if ext == "jar"
-                        {
-                            entries.push(entry.path().to_string_lossy().to_string());
-                        }
+                        entries.push(entry.path().to_string_lossy().to_string());
                     }
                 }
             }
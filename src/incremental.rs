@@ -1,24 +1,34 @@
 use crate::config::*;
-use std::path::PathBuf;
+use crate::fs::*;
+use crate::classfile::*;
+use crate::spawn::{ORANGE, RED, RESET};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::fs;
 use xxhash_rust::xxh3::xxh3_64;
 
+/// Returns whether `p` changed since the last successful build, staging its
+/// new hash in `staged`. Nothing is written to the cache here.
 #[inline(always)]
-pub fn check_incremental(p: &PathBuf, config: &mut Config) -> bool
+pub fn check_incremental(p: &PathBuf, config: &Config, staged: &mut HashMap<String, String>) -> bool
 {
     let s = p.to_string_lossy().to_string();
-    let files = &mut config.cache.files;
     if let Ok(content) = fs::read_to_string(p)
     {
         let hash = xxh3_64(content.as_bytes());
-        match files.get(&s)
+        staged.insert(s.clone(), hash.to_string());
+        if config.cache.files.get(&s).and_then(|prev| prev.parse::<u64>().ok()) != Some(hash)
         {
-            Some(prev) if prev.parse::<u64>().unwrap_or_default() == hash => false,
-            _ =>
+            return true;
+        }
+        match missing_outputs(&s, config).first()
+        {
+            Some(class) =>
             {
-                files.insert(s, hash.to_string());
+                eprintln!("{ORANGE}[INCREMENTAL]{RESET} {class} is missing, rebuilding {s}");
                 true
             }
+            None => false,
         }
     }
     else
@@ -26,3 +36,242 @@ pub fn check_incremental(p: &PathBuf, config: &mut Config) -> bool
         true
     }
 }
+
+/// Recorded class files of `src` that are no longer in `paths.bin`.
+#[inline(always)]
+pub fn missing_outputs(src: &str, config: &Config) -> Vec<String>
+{
+    config.cache.outputs.get(src)
+        .map(|x| x.keys().filter(|c| !Path::new(c).exists()).cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Recorded class files of `src` whose contents changed since they were emitted.
+#[inline(always)]
+pub fn modified_outputs(src: &str, config: &Config) -> Vec<String>
+{
+    config.cache.outputs.get(src)
+        .map(|x| x.iter()
+            .filter(|(c, h)| fs::read(c).is_ok_and(|b| xxh3_64(&b).to_string() != **h))
+            .map(|(c, _)| c.clone())
+            .collect())
+        .unwrap_or_default()
+}
+
+/// Removes the recorded class files of `src` and forgets everything the cache
+/// knows about it.
+pub fn forget_source(src: &str, config: &mut Config)
+{
+    if let Some(outputs) = config.cache.outputs.remove(src)
+    {
+        for class in outputs.keys()
+        {
+            match fs::remove_file(class)
+            {
+                Ok(_) => eprintln!("{ORANGE}[PRUNE]{RESET} removed {class}"),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+                Err(e) => eprintln!("{RED}[PRUNE]{RESET} failed to remove {class}: {e}"),
+            }
+        }
+    }
+    config.cache.files.remove(src);
+    config.cache.deps.remove(src);
+}
+
+/// Changed files plus every known source that transitively depends on them.
+pub fn dirty_files(files: Vec<PathBuf>, config: &Config, staged: &mut HashMap<String, String>) -> Vec<PathBuf>
+{
+    let mut out: Vec<PathBuf> = files.into_iter()
+        .filter(|x| check_incremental(x, config, staged))
+        .collect();
+    let mut seen: HashSet<String> = out.iter()
+        .map(|x| x.to_string_lossy().to_string())
+        .collect();
+    let mut queue: Vec<String> = seen.iter().cloned().collect();
+    while let Some(changed) = queue.pop()
+    {
+        for (src, deps) in &config.cache.deps
+        {
+            if deps.contains(&changed) && !seen.contains(src) && Path::new(src).exists()
+            {
+                eprintln!("{ORANGE}[INCREMENTAL]{RESET} {src} depends on {changed}");
+                seen.insert(src.clone());
+                queue.push(src.clone());
+                out.push(PathBuf::from(src));
+            }
+        }
+    }
+    out
+}
+
+/// `src` and every source it transitively depends on, with the hash each
+/// was last compiled from.
+pub fn touched_sources(src: &str, config: &Config) -> HashMap<String, String>
+{
+    let mut out: HashMap<String, String> = HashMap::new();
+    let mut queue = vec![src.to_string()];
+    while let Some(file) = queue.pop()
+    {
+        if out.contains_key(&file)
+        {
+            continue;
+        }
+        queue.extend(config.cache.deps.get(&file).into_iter().flatten().cloned());
+        let hash = config.cache.files.get(&file).cloned().unwrap_or_default();
+        out.insert(file, hash);
+    }
+    out
+}
+
+/// Whether a source touched by test `class` changed since it last passed,
+/// hashing the sources on disk. Tests that never passed count as changed.
+pub fn test_changed(class: &str, config: &Config) -> bool
+{
+    config.cache.test_sources.get(class)
+        .is_none_or(|sources| sources.iter().any(|(src, hash)|
+            fs::read(src).map_or(true, |b| xxh3_64(&b).to_string() != *hash)))
+}
+
+/// Merges the hashes staged for a compilation into the cache. Only call this
+/// once javac has succeeded, so failed files stay dirty.
+pub fn commit_staged(staged: HashMap<String, String>, config: &mut Config)
+{
+    config.cache.files.extend(staged);
+    record_dependencies(config);
+}
+
+#[inline(always)]
+fn  all_sources(config: &Config) -> Vec<PathBuf>
+{
+    let mut sources = fetch_files_under(&PathBuf::from(&config.paths.src), &config.src_ext);
+    sources.extend(fetch_files_under(&PathBuf::from(&config.paths.test), &config.src_ext));
+    sources.extend(fetch_files_under(&PathBuf::from(SUPPORT_DIR), &config.src_ext));
+    sources
+}
+
+/// (package dir, file name) pair that a source shares with its class files'
+/// `SourceFile` attribute.
+#[inline(always)]
+fn  source_key(p: &Path, config: &Config) -> Option<(PathBuf, String)>
+{
+    Some((source_package(p, config)?, p.file_name()?.to_string_lossy().to_string()))
+}
+
+#[inline(always)]
+fn  class_key(info: &ClassInfo) -> Option<(PathBuf, String)>
+{
+    let pkg = Path::new(&info.name).parent().map(Path::to_path_buf).unwrap_or_default();
+    Some((pkg, info.source_file.clone()?))
+}
+
+/// Deletes the class files in `paths.bin` whose `SourceFile` names one of
+/// `sources`, for sources whose outputs were never recorded.
+fn  remove_orphan_classes(sources: &[&String], config: &Config)
+{
+    let live: HashSet<(PathBuf, String)> = all_sources(config).iter()
+        .filter_map(|x| source_key(x, config))
+        .collect();
+    let gone: HashSet<(PathBuf, String)> = sources.iter()
+        .filter_map(|x| source_key(Path::new(x), config))
+        .filter(|x| !live.contains(x))
+        .collect();
+    if gone.is_empty()
+    {
+        return;
+    }
+    for class in fetch_files_under(&PathBuf::from(&config.paths.bin), &"class".to_string())
+    {
+        if read_class(&class).and_then(|x| class_key(&x)).is_some_and(|x| gone.contains(&x))
+        {
+            match fs::remove_file(&class)
+            {
+                Ok(_) => eprintln!("{ORANGE}[PRUNE]{RESET} removed {}", class.display()),
+                Err(e) => eprintln!("{RED}[PRUNE]{RESET} failed to remove {}: {e}", class.display()),
+            }
+        }
+    }
+}
+
+/// Drops cache entries for sources that no longer exist and deletes the class
+/// files they produced. Their dependents lose their hash so they get rebuilt.
+pub fn prune_deleted(config: &mut Config) -> Vec<String>
+{
+    let mut removed: Vec<String> = config.cache.files.keys()
+        .filter(|x| !Path::new(x).exists())
+        .cloned()
+        .collect();
+    removed.sort();
+
+    // Locks older than `outputs`, or classes that were never recorded.
+    let unrecorded: Vec<&String> = removed.iter()
+        .filter(|x| config.cache.outputs.get(*x).is_none_or(HashMap::is_empty))
+        .collect();
+    remove_orphan_classes(&unrecorded, config);
+
+    for src in &removed
+    {
+        eprintln!("{ORANGE}[PRUNE]{RESET} {src} no longer exists, dropped from cache");
+        forget_source(src, config);
+        let dependents: Vec<String> = config.cache.deps.iter()
+            .filter(|(_, v)| v.contains(src))
+            .map(|(k, _)| k.clone())
+            .collect();
+        for dependent in dependents
+        {
+            config.cache.files.remove(&dependent);
+        }
+    }
+    removed
+}
+
+/// Rebuilds the source dependency graph and the source -> class file map from
+/// the class files in `paths.bin`.
+pub fn record_dependencies(config: &mut Config)
+{
+    let mut owners: HashMap<(PathBuf, String), String> = HashMap::new();
+    for src in all_sources(config)
+    {
+        if let Some(key) = source_key(&src, config)
+        {
+            owners.insert(key, src.to_string_lossy().to_string());
+        }
+    }
+
+    let mut class_owner: HashMap<String, String> = HashMap::new();
+    let mut class_refs: Vec<(String, HashSet<String>)> = Vec::new();
+    let mut outputs: HashMap<String, HashMap<String, String>> = HashMap::new();
+    for class in fetch_files_under(&PathBuf::from(&config.paths.bin), &"class".to_string())
+    {
+        let Ok(buf) = fs::read(&class) else { continue };
+        let Some(info) = parse_class(&buf) else { continue };
+        if let Some(src) = class_key(&info).and_then(|x| owners.get(&x))
+        {
+            outputs.entry(src.clone()).or_default()
+                .insert(class.to_string_lossy().to_string(), xxh3_64(&buf).to_string());
+            class_owner.insert(info.name, src.clone());
+            class_refs.push((src.clone(), info.refs));
+        }
+    }
+
+    let mut deps: HashMap<String, HashSet<String>> = HashMap::new();
+    for (src, refs) in class_refs
+    {
+        for r in refs
+        {
+            if let Some(dep) = class_owner.get(&r)
+                && *dep != src
+            {
+                deps.entry(src.clone()).or_default().insert(dep.clone());
+            }
+        }
+    }
+    config.cache.outputs = outputs;
+    config.cache.deps = deps.into_iter()
+        .map(|(k, v)|
+        {
+            let mut v: Vec<String> = v.into_iter().collect();
+            v.sort();
+            (k, v)
+        })
+        .collect();
+}
@@ -0,0 +1,597 @@
+use crate::classfile::Reader;
+use std::collections::{BTreeMap, HashMap};
+
+/// Runtime class every probe calls into.
+pub const COVERAGE_CLASS: &str = "lvjb/Coverage";
+
+/// `ldc_w <id>` followed by `invokestatic lvjb/Coverage.hit(I)V`.
+const PROBE_LEN: usize = 6;
+
+/// Constants appended to a class's pool by the instrumentation.
+struct Pool
+{
+    next:   u16,
+    extra:  Vec<u8>,
+    ints:   HashMap<u32, u16>,
+    hit:    u16,
+}
+
+impl Pool
+{
+    #[inline(always)]
+    fn  add(&mut self, entry: &[u8]) -> Option<u16>
+    {
+        let index = self.next;
+        self.next = self.next.checked_add(1)?;
+        self.extra.extend_from_slice(entry);
+        Some(index)
+    }
+
+    #[inline(always)]
+    fn  utf8(&mut self, s: &str) -> Option<u16>
+    {
+        let mut entry = vec![1];
+        entry.extend_from_slice(&u16::try_from(s.len()).ok()?.to_be_bytes());
+        entry.extend_from_slice(s.as_bytes());
+        self.add(&entry)
+    }
+
+    #[inline(always)]
+    fn  pair(&mut self, tag: u8, a: u16, b: u16) -> Option<u16>
+    {
+        let [a0, a1] = a.to_be_bytes();
+        let [b0, b1] = b.to_be_bytes();
+        self.add(&[tag, a0, a1, b0, b1])
+    }
+
+    #[inline(always)]
+    fn  int(&mut self, value: u32) -> Option<u16>
+    {
+        if let Some(i) = self.ints.get(&value)
+        {
+            return Some(*i);
+        }
+        let [a, b, c, d] = value.to_be_bytes();
+        let index = self.add(&[3, a, b, c, d])?;
+        self.ints.insert(value, index);
+        Some(index)
+    }
+}
+
+/// Probe ids of one class, one per source line, handed out from `first`.
+struct Lines
+{
+    first:  u32,
+    lines:  Vec<u16>,
+    ids:    HashMap<u16, u32>,
+}
+
+impl Lines
+{
+    #[inline(always)]
+    fn  id(&mut self, line: u16) -> u32
+    {
+        *self.ids.entry(line).or_insert_with(||
+        {
+            self.lines.push(line);
+            self.first + self.lines.len() as u32 - 1
+        })
+    }
+
+    /// Forgets the ids handed out since there were `len` of them.
+    #[inline(always)]
+    fn  truncate(&mut self, len: usize)
+    {
+        let end = self.first + len as u32;
+        self.lines.truncate(len);
+        self.ids.retain(|_, id| *id < end);
+    }
+}
+
+/// Length of the instruction at `pc`, `tableswitch`/`lookupswitch` padding included.
+fn  insn_len(code: &[u8], pc: usize) -> Option<usize>
+{
+    let pad = 3 - pc % 4;
+    let int_at = |at: usize| code.get(at..at + 4).map(|b| i32::from_be_bytes([b[0], b[1], b[2], b[3]]) as i64);
+    Some(match *code.get(pc)?
+    {
+        0x10 | 0x12 | 0x15..=0x19 | 0x36..=0x3a | 0xa9 | 0xbc => 2,
+        0x11 | 0x13 | 0x14 | 0x84 | 0x99..=0xa8 | 0xb2..=0xb8 | 0xbb | 0xbd | 0xc0 | 0xc1 | 0xc6 | 0xc7 => 3,
+        0xc5 => 4,
+        0xb9 | 0xba | 0xc8 | 0xc9 => 5,
+        0xc4 => if *code.get(pc + 1)? == 0x84 { 6 } else { 4 },
+        0xaa =>
+        {
+            let base = pc + 1 + pad;
+            let cases = usize::try_from(int_at(base + 8)? - int_at(base + 4)? + 1).ok()?;
+            1 + pad + 12 + 4 * cases
+        }
+        0xab => 1 + pad + 8 + 8 * usize::try_from(int_at(pc + 1 + pad + 4)?).ok()?,
+        _ => 1,
+    })
+}
+
+/// Copies one verification type, moving `Uninitialized` offsets along with
+/// their `new` instruction.
+fn  verification_type(r: &mut Reader, out: &mut Vec<u8>, new_pc: &[Option<usize>]) -> Option<()>
+{
+    let tag = r.bytes(1)?[0];
+    out.push(tag);
+    match tag
+    {
+        0..=6 => (),
+        7 => out.extend_from_slice(r.bytes(2)?),
+        8 =>
+        {
+            let offset = (*new_pc.get(r.u2()? as usize)?)?;
+            out.extend_from_slice(&u16::try_from(offset).ok()?.to_be_bytes());
+        }
+        _ => return None,
+    }
+    Some(())
+}
+
+/// Re-encodes a `StackMapTable` for the moved code. Frames keep their kind
+/// unless their offset delta no longer fits it.
+fn  stack_map(data: &[u8], target: &impl Fn(usize) -> Option<usize>, new_pc: &[Option<usize>]) -> Option<Vec<u8>>
+{
+    let mut r = Reader { buf: data, pos: 0 };
+    let frames = r.u2()?;
+    let mut out = frames.to_be_bytes().to_vec();
+    let mut prev: Option<(usize, usize)> = None;
+    for _ in 0..frames
+    {
+        let tag = r.bytes(1)?[0];
+        let delta = match tag
+        {
+            0..=63 => tag as usize,
+            64..=127 => tag as usize - 64,
+            247..=255 => r.u2()? as usize,
+            _ => return None,
+        };
+        let offset = prev.map_or(delta, |(old, _)| old + delta + 1);
+        let moved = target(offset)?;
+        let delta = u16::try_from(prev.map_or(Some(moved), |(_, new)| moved.checked_sub(new + 1))?).ok()?;
+        prev = Some((offset, moved));
+        let [d0, d1] = delta.to_be_bytes();
+        match tag
+        {
+            0..=63 if delta <= 63 => out.push(delta as u8),
+            0..=63 => out.extend_from_slice(&[251, d0, d1]),
+            64..=127 | 247 =>
+            {
+                match tag != 247 && delta <= 63
+                {
+                    true => out.push(64 + delta as u8),
+                    false => out.extend_from_slice(&[247, d0, d1]),
+                }
+                verification_type(&mut r, &mut out, new_pc)?;
+            }
+            248..=251 => out.extend_from_slice(&[tag, d0, d1]),
+            252..=254 =>
+            {
+                out.extend_from_slice(&[tag, d0, d1]);
+                for _ in 0..tag - 251
+                {
+                    verification_type(&mut r, &mut out, new_pc)?;
+                }
+            }
+            _ =>
+            {
+                out.extend_from_slice(&[tag, d0, d1]);
+                for _ in 0..2
+                {
+                    let count = r.u2()?;
+                    out.extend_from_slice(&count.to_be_bytes());
+                    for _ in 0..count
+                    {
+                        verification_type(&mut r, &mut out, new_pc)?;
+                    }
+                }
+            }
+        }
+    }
+    Some(out)
+}
+
+/// Puts a probe in front of every instruction that starts a line in a `Code`
+/// attribute, and moves branches, switches, exception ranges, line and local
+/// variable tables and stack map frames along. Returns `None` for code it
+/// can't rewrite, which then stays as it was.
+fn  instrument_code(data: &[u8], names: &HashMap<u16, &str>, pool: &mut Pool, lines: &mut Lines) -> Option<Vec<u8>>
+{
+    let mut r = Reader { buf: data, pos: 0 };
+    let max_stack = r.u2()?.checked_add(1)?;
+    let max_locals = r.u2()?;
+    let len = r.u4()? as usize;
+    let code = r.bytes(len)?;
+    let handlers = r.u2()?;
+    let table = r.bytes(handlers as usize * 8)?;
+    let mut attrs: Vec<(u16, &[u8])> = Vec::new();
+    for _ in 0..r.u2()?
+    {
+        let name = r.u2()?;
+        let size = r.u4()? as usize;
+        attrs.push((name, r.bytes(size)?));
+    }
+    let name_of = |i: u16| names.get(&i).copied().unwrap_or_default();
+
+    let mut probes: BTreeMap<usize, u16> = BTreeMap::new();
+    for (_, data) in attrs.iter().filter(|(n, _)| name_of(*n) == "LineNumberTable")
+    {
+        let mut t = Reader { buf: data, pos: 0 };
+        for _ in 0..t.u2()?
+        {
+            let pc = t.u2()? as usize;
+            let line = t.u2()?;
+            probes.entry(pc).or_insert(line);
+        }
+    }
+    if probes.is_empty()
+    {
+        return None;
+    }
+
+    let mut starts = Vec::new();
+    let mut pc = 0;
+    while pc < len
+    {
+        starts.push(pc);
+        pc += insn_len(code, pc)?;
+    }
+    if pc != len
+    {
+        return None;
+    }
+    let mut new_pc: Vec<Option<usize>> = vec![None; len + 1];
+    let mut probe_pc: Vec<Option<usize>> = vec![None; len + 1];
+    let mut pos = 0;
+    for &pc in &starts
+    {
+        if probes.contains_key(&pc)
+        {
+            probe_pc[pc] = Some(pos);
+            pos += PROBE_LEN;
+        }
+        new_pc[pc] = Some(pos);
+        let n = insn_len(code, pc)?;
+        pos += match code[pc]
+        {
+            0xaa | 0xab => n - (3 - pc % 4) + (3 - pos % 4),
+            _ => n,
+        };
+    }
+    new_pc[len] = Some(pos);
+    if pos > u16::MAX as usize || probes.keys().any(|pc| new_pc.get(*pc).is_none_or(Option::is_none))
+    {
+        return None;
+    }
+    // Jumps to the start of a line go through its probe.
+    let target = |t: usize| probe_pc.get(t).copied().flatten().or(new_pc.get(t).copied().flatten());
+
+    let mut out: Vec<u8> = Vec::with_capacity(pos);
+    for &pc in &starts
+    {
+        if let Some(line) = probes.get(&pc)
+        {
+            let [i0, i1] = pool.int(lines.id(*line))?.to_be_bytes();
+            let [h0, h1] = pool.hit.to_be_bytes();
+            out.extend_from_slice(&[0x13, i0, i1, 0xb8, h0, h1]);
+        }
+        let here = out.len();
+        let n = insn_len(code, pc)?;
+        let relative = |offset: i64| -> Option<i64>
+        {
+            Some(target(usize::try_from(pc as i64 + offset).ok()?)? as i64 - here as i64)
+        };
+        let int_at = |at: usize| i32::from_be_bytes([code[at], code[at + 1], code[at + 2], code[at + 3]]) as i64;
+        let op = code[pc];
+        match op
+        {
+            0x99..=0xa8 | 0xc6 | 0xc7 =>
+            {
+                let offset = i16::from_be_bytes([code[pc + 1], code[pc + 2]]) as i64;
+                out.push(op);
+                out.extend_from_slice(&i16::try_from(relative(offset)?).ok()?.to_be_bytes());
+            }
+            0xc8 | 0xc9 =>
+            {
+                out.push(op);
+                out.extend_from_slice(&i32::try_from(relative(int_at(pc + 1))?).ok()?.to_be_bytes());
+            }
+            0xaa | 0xab =>
+            {
+                out.push(op);
+                out.resize(out.len() + 3 - here % 4, 0);
+                let base = pc + 1 + (3 - pc % 4);
+                out.extend_from_slice(&i32::try_from(relative(int_at(base))?).ok()?.to_be_bytes());
+                let (mut at, cases) = match op
+                {
+                    0xaa =>
+                    {
+                        out.extend_from_slice(&code[base + 4..base + 12]);
+                        (base + 12, (int_at(base + 8) - int_at(base + 4) + 1) as usize)
+                    }
+                    _ =>
+                    {
+                        out.extend_from_slice(&code[base + 4..base + 8]);
+                        (base + 8, int_at(base + 4) as usize)
+                    }
+                };
+                for _ in 0..cases
+                {
+                    if op == 0xab
+                    {
+                        out.extend_from_slice(&code[at..at + 4]);
+                        at += 4;
+                    }
+                    out.extend_from_slice(&i32::try_from(relative(int_at(at))?).ok()?.to_be_bytes());
+                    at += 4;
+                }
+            }
+            _ => out.extend_from_slice(&code[pc..pc + n]),
+        }
+    }
+
+    let mut result = Vec::with_capacity(out.len() + data.len() - len + 16);
+    result.extend_from_slice(&max_stack.to_be_bytes());
+    result.extend_from_slice(&max_locals.to_be_bytes());
+    result.extend_from_slice(&(out.len() as u32).to_be_bytes());
+    result.extend_from_slice(&out);
+    result.extend_from_slice(&handlers.to_be_bytes());
+    let mut t = Reader { buf: table, pos: 0 };
+    for _ in 0..handlers
+    {
+        for _ in 0..3
+        {
+            result.extend_from_slice(&u16::try_from(target(t.u2()? as usize)?).ok()?.to_be_bytes());
+        }
+        result.extend_from_slice(t.bytes(2)?);
+    }
+
+    // Other attributes of the code (type annotations, ...) point into the
+    // old code and are dropped.
+    let mut kept: Vec<(u16, Vec<u8>)> = Vec::new();
+    for (name, data) in attrs
+    {
+        let mut t = Reader { buf: data, pos: 0 };
+        let rewritten = match name_of(name)
+        {
+            "LineNumberTable" =>
+            {
+                let count = t.u2()?;
+                let mut x = count.to_be_bytes().to_vec();
+                for _ in 0..count
+                {
+                    x.extend_from_slice(&u16::try_from(target(t.u2()? as usize)?).ok()?.to_be_bytes());
+                    x.extend_from_slice(t.bytes(2)?);
+                }
+                x
+            }
+            "LocalVariableTable" | "LocalVariableTypeTable" =>
+            {
+                let count = t.u2()?;
+                let mut x = count.to_be_bytes().to_vec();
+                for _ in 0..count
+                {
+                    let start = t.u2()? as usize;
+                    let end = start + t.u2()? as usize;
+                    let (start, end) = (target(start)?, target(end)?);
+                    x.extend_from_slice(&u16::try_from(start).ok()?.to_be_bytes());
+                    x.extend_from_slice(&u16::try_from(end - start).ok()?.to_be_bytes());
+                    x.extend_from_slice(t.bytes(6)?);
+                }
+                x
+            }
+            "StackMapTable" => stack_map(data, &target, &new_pc)?,
+            _ => continue,
+        };
+        kept.push((name, rewritten));
+    }
+    result.extend_from_slice(&(kept.len() as u16).to_be_bytes());
+    for (name, data) in kept
+    {
+        result.extend_from_slice(&name.to_be_bytes());
+        result.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        result.extend_from_slice(&data);
+    }
+    Some(result)
+}
+
+/// Adds line probes calling `lvjb.Coverage.hit(id)` to every method of a
+/// class file. Ids start at `first_id`, one per source line. Returns the new
+/// class file and the line of each id, or `None` if the class has no line
+/// numbers or can't be read.
+pub fn  instrument_class(buf: &[u8], first_id: u32) -> Option<(Vec<u8>, Vec<u16>)>
+{
+    let mut r = Reader { buf, pos: 0 };
+    if r.u4()? != 0xCAFEBABE
+    {
+        return None;
+    }
+    r.u4()?;
+    let count = r.u2()?;
+    let pool_start = r.pos;
+    let mut names: HashMap<u16, &str> = HashMap::new();
+    let mut i = 1;
+    while i < count
+    {
+        let tag = r.bytes(1)?[0];
+        match tag
+        {
+            1 =>
+            {
+                let len = r.u2()? as usize;
+                if let Ok(s) = std::str::from_utf8(r.bytes(len)?)
+                {
+                    names.insert(i, s);
+                }
+            }
+            7 | 8 | 16 | 19 | 20 => { r.bytes(2)?; }
+            15 => { r.bytes(3)?; }
+            3 | 4 | 9 | 10 | 11 | 12 | 17 | 18 => { r.bytes(4)?; }
+            5 | 6 =>
+            {
+                r.bytes(8)?;
+                i += 1;
+            }
+            _ => return None,
+        }
+        i += 1;
+    }
+    let pool_end = r.pos;
+
+    let mut pool = Pool { next: count, extra: Vec::new(), ints: HashMap::new(), hit: 0 };
+    let class_name = pool.utf8(COVERAGE_CLASS)?;
+    let class = pool.add(&[7, (class_name >> 8) as u8, class_name as u8])?;
+    let (name, desc) = (pool.utf8("hit")?, pool.utf8("(I)V")?);
+    let nat = pool.pair(12, name, desc)?;
+    pool.hit = pool.pair(10, class, nat)?;
+
+    r.bytes(6)?;
+    let interfaces = r.u2()? as usize;
+    r.bytes(interfaces * 2)?;
+    for _ in 0..r.u2()?
+    {
+        r.bytes(6)?;
+        for _ in 0..r.u2()?
+        {
+            r.u2()?;
+            let len = r.u4()? as usize;
+            r.bytes(len)?;
+        }
+    }
+    let methods_start = r.pos;
+
+    let mut lines = Lines { first: first_id, lines: Vec::new(), ids: HashMap::new() };
+    let methods = r.u2()?;
+    let mut out_methods = methods.to_be_bytes().to_vec();
+    for _ in 0..methods
+    {
+        out_methods.extend_from_slice(r.bytes(6)?);
+        let attrs = r.u2()?;
+        out_methods.extend_from_slice(&attrs.to_be_bytes());
+        for _ in 0..attrs
+        {
+            let name = r.u2()?;
+            let len = r.u4()? as usize;
+            let data = r.bytes(len)?;
+            let known = lines.lines.len();
+            let code = match names.get(&name) == Some(&"Code")
+            {
+                true => instrument_code(data, &names, &mut pool, &mut lines),
+                false => None,
+            };
+            if code.is_none()
+            {
+                lines.truncate(known);
+            }
+            let data = code.as_deref().unwrap_or(data);
+            out_methods.extend_from_slice(&name.to_be_bytes());
+            out_methods.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            out_methods.extend_from_slice(data);
+        }
+    }
+    if lines.lines.is_empty()
+    {
+        return None;
+    }
+
+    let mut out = Vec::with_capacity(buf.len() + pool.extra.len() + 64);
+    out.extend_from_slice(&buf[..8]);
+    out.extend_from_slice(&pool.next.to_be_bytes());
+    out.extend_from_slice(&buf[pool_start..pool_end]);
+    out.extend_from_slice(&pool.extra);
+    out.extend_from_slice(&buf[pool_end..methods_start]);
+    out.extend_from_slice(&out_methods);
+    out.extend_from_slice(&buf[r.pos..]);
+    Some((out, lines.lines))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::classfile::tests::javac;
+    use crate::cache::Cache;
+    use crate::config::Config;
+    use crate::coverage::{Recorder, COVERAGE};
+    use crate::jvm::*;
+    use jni::objects::JValue;
+    use std::fs;
+
+    const FLOW: &str = "package fx;
+public class Flow {
+    public static int run(int n) {
+        int sum = 0;
+        for (int i = 0; i < n; i++) {
+            if (i % 2 == 0) sum += i;
+            else sum -= 1;
+        }
+        try {
+            if (n > 3) throw new IllegalStateException();
+            sum += 100;
+        } catch (IllegalStateException e) {
+            sum += 1000;
+        } finally {
+            sum += 5;
+        }
+        switch (n) { case 0: sum += 1; break; case 1: sum += 2; break; case 2: sum += 3; break; default: sum += 4; }
+        switch (n) { case 5: sum *= 2; break; case 1000: sum *= 3; break; }
+        while (sum > 1500) sum /= 2;
+        return sum;
+    }
+
+    public static int never() {
+        return 42;
+    }
+}
+";
+
+    #[test]
+    fn  instrumented_class_verifies_and_records_hits()
+    {
+        let bin = javac("instrument", &[("fx/Flow.java", FLOW), ("lvjb/Coverage.java", COVERAGE)]);
+        let (out, lines) = instrument_class(&fs::read(bin.join("fx/Flow.class")).unwrap(), 0).expect("Flow.class should instrument");
+        let instrumented = bin.with_file_name("instrumented");
+        fs::create_dir_all(instrumented.join("fx")).unwrap();
+        fs::write(instrumented.join("fx/Flow.class"), out).unwrap();
+
+        let line_of = |text: &str| FLOW.lines().position(|l| l.contains(text)).unwrap() as u16 + 1;
+        let plain = Config { classpath: vec![bin.to_string_lossy().into_owned()], ..Config::with_cache(Cache::default()) };
+        let probed = Config { classpath: vec![instrumented.to_string_lossy().into_owned(), bin.to_string_lossy().into_owned()], ..Config::with_cache(Cache::default()) };
+        let recorder = Recorder::Probes { classes: instrumented.to_string_lossy().into_owned(), probes: lines.len() };
+
+        let jvm = shared_jvm(&plain).unwrap();
+        recorder.start(jvm).unwrap();
+        let mut env = jvm.attach_current_thread().unwrap();
+        for n in [0, 1, 2, 3, 5, 1000]
+        {
+            let mut run = |config: &Config|
+            {
+                let loader = class_loader(&mut env, config).unwrap();
+                let result = load_class(&mut env, &loader, "fx.Flow")
+                    .and_then(|class| env.call_static_method(&class, "run", "(I)I", &[JValue::Int(n)]))
+                    .and_then(|v| v.i());
+                result.unwrap_or_else(|e| panic!("run({n}): {e}: {}", describe_exception(&mut env).unwrap_or_default()))
+            };
+            assert_eq!(run(&probed), run(&plain), "run({n})");
+        }
+        drop(env);
+
+        let hit: Vec<u16> = recorder.finish(jvm).unwrap().into_iter().map(|id| lines[id as usize]).collect();
+        for text in ["int sum = 0", "sum -= 1", "throw new", "sum += 1000", "sum += 5", "sum *= 3", "sum /= 2", "return sum"]
+        {
+            assert!(hit.contains(&line_of(text)), "line of '{text}' wasn't hit");
+        }
+        assert!(lines.contains(&line_of("return 42")));
+        assert!(!hit.contains(&line_of("return 42")));
+        let _ = fs::remove_dir_all(bin.parent().unwrap());
+    }
+
+    #[test]
+    fn  rejects_other_files()
+    {
+        assert!(instrument_class(b"not a class", 0).is_none());
+    }
+}
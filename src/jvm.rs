@@ -1,12 +1,43 @@
 use jni::*;
+use jni::objects::{JClass, JObject, JString, JValue};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
 use crate::config::*;
 use crate::fs::*;
 
+/// Major version of the runtime the VM is loaded from, found as the jni
+/// crate finds it: `$JAVA_HOME`, else the `java` on the `PATH`. Read from
+/// its `release` file, `None` if there is none.
+fn  java_major() -> Option<u32>
+{
+    let home = match env::var_os("JAVA_HOME")
+    {
+        Some(home) => PathBuf::from(home),
+        None =>
+        {
+            let java = env::split_paths(&env::var_os("PATH")?).map(|dir| dir.join("java")).find(|x| x.is_file())?;
+            java.canonicalize().ok()?.parent()?.parent()?.to_path_buf()
+        }
+    };
+    // A JDK 8 `java` may live in its `jre/`.
+    let release = [home.join("release"), home.parent()?.join("release")].into_iter().find_map(|x| fs::read_to_string(x).ok())?;
+    let version = release.lines().find_map(|l| l.strip_prefix("JAVA_VERSION="))?.trim_matches('"');
+    version.strip_prefix("1.").unwrap_or(version).split(['.', '-', '+', '_']).next()?.parse().ok()
+}
+
 pub fn  spawn_jvm(config: &Config) -> Result<JavaVM, Box<dyn std::error::Error>>
 {
     let mut builder = InitArgsBuilder::new()
           .version(JNIVersion::V8)
           .option("-Xcheck:jni");
+    // Lets lvjb.ExitGuard turn System.exit into an exception on JDK 18 to 23.
+    // Earlier JDKs allow it by default, later ones refuse to start with it.
+    if matches!(java_major(), Some(18..=23))
+    {
+        builder = builder.option("-Djava.security.manager=allow");
+    }
     let classpath = format!(
         "-Djava.class.path={}",
         expand_classpath(&config.classpath));
@@ -22,3 +53,89 @@ pub fn  spawn_jvm(config: &Config) -> Result<JavaVM, Box<dyn std::error::Error>>
     let jvm = JavaVM::new(jvm_args)?;
     Ok(jvm)
 }
+
+static JVM: OnceLock<JavaVM> = OnceLock::new();
+
+/// Process-wide VM. JNI only allows one VM per process, so the compiler, the
+/// runner and the test runner all share it.
+pub fn  shared_jvm(config: &Config) -> Result<&'static JavaVM, Box<dyn std::error::Error>>
+{
+    if let Some(jvm) = JVM.get()
+    {
+        return Ok(jvm);
+    }
+    let jvm = spawn_jvm(config)?;
+    Ok(JVM.get_or_init(|| jvm))
+}
+
+/// Whether the process-wide VM is up, so VM options can no longer change.
+#[inline(always)]
+pub fn  jvm_started() -> bool
+{
+    JVM.get().is_some()
+}
+
+/// Clears the pending Java exception and returns its `toString()`.
+pub fn  describe_exception(env: &mut JNIEnv) -> Result<String, Box<dyn std::error::Error>>
+{
+    if !env.exception_check()?
+    {
+        return Err("Java exception occurred, but couldn't get details".into());
+    }
+    let exception = env.exception_occurred()?;
+    env.exception_clear()?;
+    let jstr = env.call_method(exception, "toString", "()Ljava/lang/String;", &[])?;
+    let msg_obj = jstr.l()?;
+    Ok(env.get_string(&JString::from(msg_obj))?.into())
+}
+
+/// Fresh `URLClassLoader` over the configured classpath. Its parent is the
+/// platform loader, so classes from `paths.bin` are always read from disk
+/// again instead of coming from a VM that outlived a recompile.
+pub fn  class_loader<'a>(env: &mut JNIEnv<'a>, config: &Config) -> Result<JObject<'a>, jni::errors::Error>
+{
+    let classpath = expand_classpath(&config.classpath);
+    let entries: Vec<&str> = classpath.split(':').filter(|x| !x.is_empty()).collect();
+    let urls = env.new_object_array(entries.len() as i32, "java/net/URL", JObject::null())?;
+    for (i, entry) in entries.iter().enumerate()
+    {
+        let path = env.new_string(entry)?;
+        let file = env.new_object("java/io/File", "(Ljava/lang/String;)V", &[JValue::Object(&path)])?;
+        let uri = env.call_method(&file, "toURI", "()Ljava/net/URI;", &[])?.l()?;
+        let url = env.call_method(&uri, "toURL", "()Ljava/net/URL;", &[])?.l()?;
+        env.set_object_array_element(&urls, i as i32, url)?;
+    }
+    let system = env.call_static_method("java/lang/ClassLoader", "getSystemClassLoader", "()Ljava/lang/ClassLoader;", &[])?.l()?;
+    let parent = env.call_method(&system, "getParent", "()Ljava/lang/ClassLoader;", &[])?.l()?;
+    env.new_object(
+        "java/net/URLClassLoader",
+        "([Ljava/net/URL;Ljava/lang/ClassLoader;)V",
+        &[JValue::Object(&urls), JValue::Object(&parent)],
+    )
+}
+
+/// Loads `name` (dotted binary name) through `loader` and makes the loader the
+/// current thread's context class loader.
+pub fn  load_class<'a>(env: &mut JNIEnv<'a>, loader: &JObject, name: &str) -> Result<JClass<'a>, jni::errors::Error>
+{
+    let thread = env.call_static_method("java/lang/Thread", "currentThread", "()Ljava/lang/Thread;", &[])?.l()?;
+    env.call_method(&thread, "setContextClassLoader", "(Ljava/lang/ClassLoader;)V", &[JValue::Object(loader)])?;
+    let jname = env.new_string(name)?;
+    let class = env.call_method(loader, "loadClass", "(Ljava/lang/String;)Ljava/lang/Class;", &[JValue::Object(&jname)])?.l()?;
+    Ok(JClass::from(class))
+}
+
+/// Flushes `System.out`/`System.err` if a VM is up.
+pub fn  flush_java_streams()
+{
+    let Some(jvm) = JVM.get() else { return };
+    let Ok(mut env) = jvm.attach_current_thread() else { return };
+    for stream in ["out", "err"]
+    {
+        if let Ok(s) = env.get_static_field("java/lang/System", stream, "Ljava/io/PrintStream;").and_then(|x| x.l())
+        {
+            let _ = env.call_method(&s, "flush", "()V", &[]);
+        }
+    }
+    let _ = env.exception_clear();
+}
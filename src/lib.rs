@@ -3,5 +3,23 @@ pub mod cache;
 pub mod fs;
 pub mod cmds;
 pub mod incremental;
+pub mod classfile;
 pub mod spawn;
+pub mod parallel;
 pub mod jvm;
+pub mod testrunner;
+pub mod report;
+pub mod watchdog;
+pub mod capture;
+pub mod scheduler;
+pub mod filter;
+pub mod instrument;
+pub mod coverage;
+pub mod maven;
+pub mod artifacts;
+pub mod libs;
+pub mod pom;
+pub mod compiler;
+pub mod diagnostics;
+pub mod daemon;
+pub mod watch;
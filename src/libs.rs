@@ -0,0 +1,499 @@
+use crate::artifacts::*;
+use crate::config::*;
+use crate::fs::*;
+use crate::maven::*;
+use crate::pom::*;
+use crate::spawn::{GREEN, ORANGE, RED, RESET};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use toml_edit::{value, DocumentMut, Value};
+
+/// How a jar got into `paths.lib`.
+#[derive(Debug, Clone)]
+enum Origin
+{
+    Declared(Artifact),
+    /// Pulled in by the POM of the `group:artifact:version` given.
+    Transitive(Artifact, String),
+    Curl(String),
+    Manual,
+}
+
+impl fmt::Display for Origin
+{
+    fn  fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            Origin::Declared(a) => write!(f, "{a}, declared"),
+            Origin::Transitive(a, by) => write!(f, "{a}, required by {by}"),
+            Origin::Curl(url) => write!(f, "curl {url}"),
+            Origin::Manual => write!(f, "put there by hand"),
+        }
+    }
+}
+
+/// Every jar in `paths.lib`, and those of the dependency tree missing from
+/// it, with where each comes from.
+fn  origins(config: &Config) -> BTreeMap<String, Origin>
+{
+    let mut out: BTreeMap<String, Origin> = fs::read_dir(&config.paths.lib).into_iter().flatten()
+        .filter_map(|e| e.ok().map(|e| e.file_name().to_string_lossy().into_owned()))
+        .filter(|name| name.ends_with(".jar") && !name.starts_with('.'))
+        .map(|name| (name, Origin::Manual))
+        .collect();
+    for url in &config.cache.url_libs
+    {
+        if let Some(file) = url.rsplit('/').next()
+        {
+            out.insert(file.to_string(), Origin::Curl(url.clone()));
+        }
+    }
+    for (key, r) in &config.cache.tree
+    {
+        let Ok(a) = Artifact::parse(key, &r.version) else { continue };
+        let origin = match &r.required_by
+        {
+            Some(by) => Origin::Transitive(a.clone(), by.clone()),
+            None => Origin::Declared(a.clone()),
+        };
+        out.insert(a.file_name(), origin);
+    }
+    for a in declared(config).unwrap_or_default()
+    {
+        out.entry(a.file_name()).or_insert(Origin::Declared(a));
+    }
+    out
+}
+
+/// `verified`, `modified` or `unverified` for a jar present in `paths.lib`,
+/// by its hash in `lvjb.lock` or `[checksums]`; `missing` otherwise.
+fn  status(file: &str, config: &Config) -> &'static str
+{
+    let Ok(bytes) = fs::read(forge_sys_path(file, config, PathType::LIB)) else { return "missing" };
+    match config.cache.libs.get(file).or(config.checksums.get(file)).map(|hash| hash_matches(&bytes, hash))
+    {
+        Some(Ok(true)) => "verified",
+        Some(Ok(false)) => "modified",
+        Some(Err(_)) | None => "unverified",
+    }
+}
+
+/// `lvjb lib list`: the jars of `paths.lib`, whether they still match their
+/// verified hash, and where they come from.
+fn  list(config: &Config) -> Result<(), Box<dyn std::error::Error>>
+{
+    let origins = origins(config);
+    let mut missing = 0;
+    let width = origins.keys().map(String::len).max().unwrap_or(0);
+    for (file, origin) in &origins
+    {
+        let status = status(file, config);
+        missing += usize::from(status == "missing");
+        let color = match status
+        {
+            "verified" => GREEN,
+            "modified" | "missing" => RED,
+            _ => ORANGE,
+        };
+        println!("  {file:<width$}  {color}{status:<10}{RESET}  {origin}");
+    }
+    match missing
+    {
+        0 => eprintln!("{GREEN}[LIB]{RESET} {} jar(s) in {}", origins.len(), config.paths.lib),
+        n => eprintln!("{ORANGE}[LIB]{RESET} {} jar(s) in {}, {n} missing, see 'lvjb fetch'", origins.len() - n, config.paths.lib),
+    }
+    Ok(())
+}
+
+/// The jar `name` refers to: a file name in `paths.lib`, a
+/// `group:artifact`, an artifact name or the URL it was fetched with.
+fn  find(name: &str, config: &Config) -> Result<(String, Origin), String>
+{
+    let mut found: Vec<(String, Origin)> = origins(config).into_iter()
+        .filter(|(file, origin)| file == name || match origin
+        {
+            Origin::Declared(a) | Origin::Transitive(a, _) => a.key() == name || a.artifact == name,
+            Origin::Curl(url) => url == name,
+            Origin::Manual => false,
+        })
+        .collect();
+    match found.len()
+    {
+        1 => Ok(found.remove(0)),
+        0 => Err(format!("{RED}[LIB]{RESET} No library '{name}' in {}, see 'lvjb lib list'", config.paths.lib)),
+        _ => Err(format!("{RED}[LIB]{RESET} '{name}' could be {}, name the jar", found.iter().map(|(f, _)| f.as_str()).collect::<Vec<_>>().join(", "))),
+    }
+}
+
+/// Sets `key` under `[dependencies]` to `version`, or removes it, in
+/// `lvjb.toml` and in `config`. The rest of the file stays as written.
+fn  set_dependency(key: &str, version: Option<&str>, config: &mut Config) -> Result<(), Box<dyn std::error::Error>>
+{
+    let mut doc: DocumentMut = fs::read_to_string(CONF_FILE)?.parse()?;
+    match version
+    {
+        Some(v) =>
+        {
+            let deps = &mut doc["dependencies"];
+            match deps.get_mut(key).and_then(|d| d.as_value_mut())
+            {
+                // Keeps the comment after the old version.
+                Some(old) =>
+                {
+                    let decor = old.decor().clone();
+                    *old = Value::from(v);
+                    *old.decor_mut() = decor;
+                }
+                None => deps[key] = value(v),
+            }
+            config.dependencies.insert(key.to_string(), v.to_string());
+        }
+        None =>
+        {
+            if let Some(deps) = doc.get_mut("dependencies").and_then(|d| d.as_table_like_mut())
+            {
+                deps.remove(key);
+            }
+            config.dependencies.remove(key);
+        }
+    }
+    fs::write(CONF_FILE, doc.to_string())?;
+    Ok(())
+}
+
+/// `lvjb lib remove <name>`: a declared dependency leaves `[dependencies]`,
+/// and `fetch` then deletes its jar and what only it needed. Other jars are
+/// deleted along with their entries in `lvjb.lock`.
+fn  remove(name: &str, config: &mut Config) -> Result<(), Box<dyn std::error::Error>>
+{
+    let (file, origin) = find(name, config)?;
+    match &origin
+    {
+        Origin::Declared(a) =>
+        {
+            set_dependency(&a.key(), None, config)?;
+            eprintln!("{ORANGE}[LIB]{RESET} Removed {} from [dependencies]", a.key());
+            return fetch(config);
+        }
+        Origin::Transitive(a, by) =>
+            return Err(format!("{RED}[LIB]{RESET} {a} is required by {by}, remove that instead").into()),
+        Origin::Curl(_) | Origin::Manual => (),
+    }
+    match fs::remove_file(forge_sys_path(&file, config, PathType::LIB))
+    {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(format!("{RED}[LIB]{RESET} Couldn't remove {file}: {e}").into()),
+        _ => (),
+    }
+    config.cache.url_libs.retain(|url| url.rsplit('/').next() != Some(file.as_str()));
+    config.cache.libs.remove(&file);
+    config.cache.fetched.remove(&file);
+    config.cache.write()?;
+    eprintln!("{GREEN}[LIB]{RESET} Removed {file} ({origin})");
+    Ok(())
+}
+
+/// Part of a version: a number, or a qualifier such as `beta`.
+#[derive(Debug, PartialEq, Eq)]
+enum Token
+{
+    Number(u64),
+    Word(String),
+}
+
+/// Splits `version` at `.`, `-`, `_` and where digits meet letters.
+fn  tokens(version: &str) -> Vec<Token>
+{
+    let mut out = Vec::new();
+    let mut current = String::new();
+    let flush = |current: &mut String, out: &mut Vec<Token>|
+    {
+        if !current.is_empty()
+        {
+            out.push(current.parse().map(Token::Number).unwrap_or_else(|_| Token::Word(current.to_ascii_lowercase())));
+            current.clear();
+        }
+    };
+    for c in version.chars()
+    {
+        if matches!(c, '.' | '-' | '_')
+        {
+            flush(&mut current, &mut out);
+            continue;
+        }
+        if current.chars().last().is_some_and(|l| l.is_ascii_digit() != c.is_ascii_digit())
+        {
+            flush(&mut current, &mut out);
+        }
+        current.push(c);
+    }
+    flush(&mut current, &mut out);
+    out
+}
+
+/// Maven's order of qualifiers; a plain release ranks as `ga`.
+#[inline(always)]
+fn  qualifier_rank(word: &str) -> u8
+{
+    match word
+    {
+        "alpha" | "a" => 0,
+        "beta" | "b" => 1,
+        "milestone" | "m" => 2,
+        "rc" | "cr" => 3,
+        "snapshot" => 4,
+        "" | "ga" | "final" | "release" => 5,
+        "sp" => 6,
+        _ => 7,
+    }
+}
+
+/// Compares versions the way Maven roughly does: number by number, a
+/// missing part counting as `0` or as a release, releases above `alpha`,
+/// `beta`, `rc` and snapshots of the same numbers.
+pub fn  compare_versions(a: &str, b: &str) -> Ordering
+{
+    let (a, b) = (tokens(a), tokens(b));
+    for i in 0..a.len().max(b.len())
+    {
+        let order = match (a.get(i), b.get(i))
+        {
+            (Some(Token::Number(x)), Some(Token::Number(y))) => x.cmp(y),
+            (Some(Token::Number(x)), None) => x.cmp(&0),
+            (None, Some(Token::Number(y))) => 0.cmp(y),
+            (Some(Token::Number(_)), Some(Token::Word(_))) => Ordering::Greater,
+            (Some(Token::Word(_)), Some(Token::Number(_))) => Ordering::Less,
+            (x, y) =>
+            {
+                let word = |t: Option<&Token>| match t
+                {
+                    Some(Token::Word(w)) => w.clone(),
+                    _ => String::new(),
+                };
+                // Spellings of a known qualifier are equal, unknown ones
+                // compare as text.
+                let (x, y) = (word(x), word(y));
+                match (qualifier_rank(&x), qualifier_rank(&y))
+                {
+                    (7, 7) => x.cmp(&y),
+                    (rx, ry) => rx.cmp(&ry),
+                }
+            }
+        };
+        if order != Ordering::Equal
+        {
+            return order;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Whether `version` is an alpha, beta, milestone, release candidate or
+/// snapshot.
+#[inline(always)]
+fn  is_prerelease(version: &str) -> bool
+{
+    tokens(version).iter().any(|t| matches!(t, Token::Word(w) if qualifier_rank(w) < 5))
+}
+
+/// Versions of `a` the repository lists in its `maven-metadata.xml`.
+fn  versions(a: &Artifact, config: &Config) -> Result<Vec<String>, Box<dyn std::error::Error>>
+{
+    let url = format!("{}/{}/{}/maven-metadata.xml", config.repository.trim_end_matches('/'), a.group.replace('.', "/"), a.artifact);
+    let metadata = parse_xml(&String::from_utf8_lossy(&download(&url)?))?;
+    let versioning = metadata.child("versioning").ok_or("maven-metadata.xml has no <versioning>")?;
+    let mut versions: Vec<String> = versioning.list("versions", "version").iter().map(|v| v.text.trim().to_string()).collect();
+    versions.extend(["release", "latest"].iter().filter_map(|k| versioning.text_of(k)).filter(|v| !versions.contains(v)).collect::<Vec<_>>());
+    Ok(versions)
+}
+
+/// The newest version of `a` above its own. Pre-releases only count when
+/// `a` is one itself, snapshots never.
+fn  newer_version(a: &Artifact, config: &Config) -> Result<Option<String>, Box<dyn std::error::Error>>
+{
+    let stable = !is_prerelease(&a.version);
+    Ok(versions(a, config)?.into_iter()
+        .filter(|v| !v.to_ascii_uppercase().ends_with("SNAPSHOT") && (!stable || !is_prerelease(v)))
+        .filter(|v| compare_versions(v, &a.version) == Ordering::Greater)
+        .max_by(|x, y| compare_versions(x, y)))
+}
+
+/// Newer versions are only known to the repository, `--offline` can't
+/// look for them.
+#[inline(always)]
+fn  online(config: &Config) -> Result<(), String>
+{
+    match config.offline && !config.repository.starts_with("file://")
+    {
+        true => Err(format!("{RED}[LIB]{RESET} Looking for newer versions needs {}, drop --offline", config.repository)),
+        false => Ok(()),
+    }
+}
+
+/// `lvjb lib outdated`: the declared dependencies with newer versions in
+/// the repository.
+fn  outdated(config: &Config) -> Result<(), Box<dyn std::error::Error>>
+{
+    online(config)?;
+    let declared = declared(config)?;
+    let width = declared.iter().map(|a| a.key().len()).max().unwrap_or(0);
+    let mut count = 0;
+    for a in &declared
+    {
+        match newer_version(a, config)
+        {
+            Ok(Some(latest)) =>
+            {
+                println!("  {:<width$}  {:>12} -> {GREEN}{latest}{RESET}", a.key(), a.version);
+                count += 1;
+            }
+            Ok(None) => (),
+            Err(e) => eprintln!("{ORANGE}[LIB]{RESET} Couldn't check {}: {e}", a.key()),
+        }
+    }
+    eprintln!("{GREEN}[LIB]{RESET} {count} of {} dependencies have newer versions", declared.len());
+    Ok(())
+}
+
+/// `lvjb lib update [name]`: moves declared dependencies (all, or `name`)
+/// to their newest version and fetches them, and downloads jars added with
+/// `curl` again.
+fn  update(name: Option<&str>, config: &mut Config) -> Result<(), Box<dyn std::error::Error>>
+{
+    let targets: Vec<(String, Origin)> = match name
+    {
+        Some(n) => vec![find(n, config)?],
+        None => origins(config).into_iter().filter(|(_, o)| matches!(o, Origin::Declared(_) | Origin::Curl(_))).collect(),
+    };
+    if targets.iter().any(|(_, o)| matches!(o, Origin::Declared(_)))
+    {
+        online(config)?;
+    }
+    let mut bumped = 0;
+    for (file, origin) in &targets
+    {
+        match origin
+        {
+            Origin::Declared(a) => match newer_version(a, config)
+            {
+                Ok(Some(latest)) =>
+                {
+                    eprintln!("{GREEN}[LIB]{RESET} {} {} -> {latest}", a.key(), a.version);
+                    set_dependency(&a.key(), Some(&latest), config)?;
+                    bumped += 1;
+                }
+                Ok(None) => eprintln!("{GREEN}[LIB]{RESET} {a} is up to date"),
+                Err(e) => eprintln!("{ORANGE}[LIB]{RESET} Couldn't check {}: {e}", a.key()),
+            },
+            Origin::Curl(url) =>
+            {
+                if config.offline && !url.starts_with("file://")
+                {
+                    return Err(format!("{RED}[LIB]{RESET} {}", Offline(file.clone())).into());
+                }
+                // The jar may have changed on purpose, only its published hash counts.
+                let recorded = config.cache.libs.remove(file);
+                eprintln!("{ORANGE}[FETCHING]{RESET} {url}");
+                match download_verified(url, file, config)
+                {
+                    Ok((bytes, hash)) =>
+                    {
+                        let _ = store(url, &bytes, &hash);
+                        let dest = forge_sys_path(file, config, PathType::LIB);
+                        if !link(&hash, &dest)
+                        {
+                            write_atomic(&dest, &bytes)?;
+                        }
+                        if recorded.as_ref() != Some(&hash)
+                        {
+                            eprintln!("{GREEN}[LIB]{RESET} {file} changed");
+                        }
+                        config.cache.libs.insert(file.clone(), hash);
+                    }
+                    Err(e) =>
+                    {
+                        if let Some(h) = recorded
+                        {
+                            config.cache.libs.insert(file.clone(), h);
+                        }
+                        return Err(format!("{RED}[LIB]{RESET} {file}: {e}").into());
+                    }
+                }
+                config.cache.write()?;
+            }
+            Origin::Transitive(a, by) =>
+                return Err(format!("{RED}[LIB]{RESET} {a} is required by {by}, declare it to pick its version").into()),
+            Origin::Manual =>
+                return Err(format!("{RED}[LIB]{RESET} {file} was put in {} by hand, there's nowhere to update it from", config.paths.lib).into()),
+        }
+    }
+    match bumped
+    {
+        0 => Ok(()),
+        _ => fetch(config),
+    }
+}
+
+/// `lvjb lib <list|remove|update|outdated>`.
+pub fn  lib(args: &[String], config: &mut Config) -> Result<(), Box<dyn std::error::Error>>
+{
+    match (args.first().map(String::as_str), args.get(1))
+    {
+        (None | Some("list"), _) => list(config),
+        (Some("remove"), Some(name)) => remove(name, config),
+        (Some("remove"), None) => Err(format!("{RED}[LIB]{RESET} Usage: lvjb lib remove <jar|group:artifact|artifact>").into()),
+        (Some("update"), name) => update(name.map(String::as_str), config),
+        (Some("outdated"), _) => outdated(config),
+        (Some(x), _) => Err(format!("{RED}[LIB]{RESET} Unknown subcommand '{x}', expected list, remove, update or outdated").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn  tokens_split_at_separators_and_digit_letter_boundaries()
+    {
+        assert_eq!(tokens("1.2-RC1"), vec![Token::Number(1), Token::Number(2), Token::Word("rc".into()), Token::Number(1)]);
+        assert_eq!(tokens("2_0beta"), vec![Token::Number(2), Token::Number(0), Token::Word("beta".into())]);
+        assert!(tokens("").is_empty());
+    }
+
+    #[test]
+    fn  qualifiers_order_around_the_release()
+    {
+        let ordered = ["1.0-alpha1", "1.0-beta", "1.0-m2", "1.0-rc1", "1.0-SNAPSHOT", "1.0", "1.0-sp1", "1.0.1", "1.10"];
+        for pair in ordered.windows(2)
+        {
+            assert_eq!(compare_versions(pair[0], pair[1]), Ordering::Less, "{} < {}", pair[0], pair[1]);
+            assert_eq!(compare_versions(pair[1], pair[0]), Ordering::Greater, "{} > {}", pair[1], pair[0]);
+        }
+    }
+
+    #[test]
+    fn  missing_parts_count_as_zero_or_release()
+    {
+        assert_eq!(compare_versions("1.0", "1.0.0"), Ordering::Equal);
+        assert_eq!(compare_versions("1", "1.0-ga"), Ordering::Equal);
+        assert_eq!(compare_versions("1.0-final", "1.0"), Ordering::Equal);
+        assert_eq!(compare_versions("1.0-cr1", "1.0-RC1"), Ordering::Equal);
+        assert_eq!(compare_versions("1.9", "1.10"), Ordering::Less);
+    }
+
+    #[test]
+    fn  prereleases_are_recognised()
+    {
+        for v in ["1.0-alpha", "1.0-b2", "2.0-M1", "3.0-rc1", "3.0-CR2", "1.0-SNAPSHOT"]
+        {
+            assert!(is_prerelease(v), "{v}");
+        }
+        for v in ["1.0", "1.0.0", "1.0-ga", "1.0-final", "1.0-sp1", "1.0-jre"]
+        {
+            assert!(!is_prerelease(v), "{v}");
+        }
+    }
+}
@@ -1,10 +1,55 @@
 use lvjb::config::*;
 use lvjb::{cmds, cmds::*};
 use lvjb::jvm::*;
+use lvjb::daemon::{self, FORWARDED};
+use lvjb::libs;
+use lvjb::maven;
+use lvjb::watch;
+use lvjb::testrunner::{self, TestOptions, EXIT_TESTS_FAILED, WORKER_CMD};
+use std::path::Path;
 use std::env;
 
 fn  _main() -> Result<(), i32> {
     let args: Vec<String> = env::args().collect();
+    if args.get(1).is_some_and(|x| FORWARDED.contains(&x.as_str()))
+        && let Some(code) = daemon::forward(&args)
+    {
+        return if code == 0 { Ok(()) } else { Err(code) };
+    }
+    dispatch(args)
+}
+
+/// Value of `-j N` or `-jN`, if given before a `--`.
+fn  jobs_flag(args: &[String]) -> Option<usize>
+{
+    let end = args.iter().position(|x| x == "--").unwrap_or(args.len());
+    let flags = &args[..end];
+    let pos = flags.iter().position(|x| x.starts_with("-j"))?;
+    match &flags[pos][2..]
+    {
+        "" => flags.get(pos + 1)?.parse().ok(),
+        n => n.parse().ok(),
+    }
+}
+
+/// Takes `--offline` out of the arguments lvjb reads itself, those before
+/// a `--`.
+fn  offline_flag(args: &mut Vec<String>) -> bool
+{
+    let end = args.iter().position(|x| x == "--").unwrap_or(args.len());
+    match args[..end].iter().position(|x| x == "--offline")
+    {
+        Some(pos) =>
+        {
+            args.remove(pos);
+            true
+        }
+        None => false,
+    }
+}
+
+fn  dispatch(mut args: Vec<String>) -> Result<(), i32> {
+    let offline = offline_flag(&mut args);
     let mut conf = match Config::load()
     {
         Ok(x) => x,
@@ -34,6 +79,15 @@ fn  _main() -> Result<(), i32> {
                 }
             },
     };
+    if let Some(fmt) = args.iter().find_map(|x| x.strip_prefix("--message-format="))
+    {
+        conf.message_format = fmt.to_string();
+    }
+    if let Some(jobs) = jobs_flag(&args)
+    {
+        conf.jobs = jobs;
+    }
+    conf.offline = offline;
     match args.get(1).map(String::as_str)
     {
         Some("init") =>
@@ -66,8 +120,8 @@ fn  _main() -> Result<(), i32> {
             {
                 conf.incremental = false;
             }
-            let pkg = args.get(2);
-            if let Err(e) = cmds::build(pkg.clone(), &mut conf)
+            let pkg = operand(&args);
+            if let Err(e) = cmds::build(pkg, &mut conf)
             {
                 eprintln!("{e}");
                 return Err(1);
@@ -77,7 +131,7 @@ fn  _main() -> Result<(), i32> {
         {
             if let Some(classname) = args.get(2)
             {
-                cmds::docgen(classname, &mut conf)
+                cmds::docgen(classname, &conf)
             }
             else
             {
@@ -101,18 +155,38 @@ fn  _main() -> Result<(), i32> {
                 return Err(1);
             }
         }
+        Some("fetch") =>
+        {
+            if let Err(e) = maven::fetch(&mut conf)
+            {
+                eprintln!("{e}");
+                return Err(1);
+            }
+        }
+        Some("lib") =>
+        {
+            if let Err(e) = libs::lib(args.get(2..).unwrap_or(&[]), &mut conf)
+            {
+                eprintln!("{e}");
+                return Err(1);
+            }
+        }
+        Some("tree") =>
+        {
+            if let Err(e) = maven::tree(&mut conf)
+            {
+                eprintln!("{e}");
+                return Err(1);
+            }
+        }
         Some("run") => {
             let extra_args_start = args.iter().position(|arg| arg == "--");
 
-            if let Ok(jvm) = spawn_jvm(&conf)
+            if let Ok(jvm) = shared_jvm(&conf)
             {
                 let pkg = match args.get(2)
                 {
-                    Some(x) if x == "--" => match conf.entry_point
-                    {
-                        Some(ref s) => Some(s.to_string()),
-                        None => None,
-                    },
+                    Some(x) if x == "--" => conf.entry_point.clone(),
                     Some(s) => Some(s.to_string()),
                     None => None,
                 };
@@ -122,13 +196,13 @@ fn  _main() -> Result<(), i32> {
                     conf.args.runtime.get_or_insert_with(Vec::new).extend(user_args);
                 }
 
-                if let Err(e) = cmds::run(pkg.as_ref(), &mut conf, &jvm, true)
+                if let Err(e) = cmds::run(pkg.as_ref(), &conf, jvm, true)
                 {
                     eprintln!("{e}");
                     return Err(1);
                 }
             }
-            else if let Err(e) = spawn_jvm(&conf)
+            else if let Err(e) = shared_jvm(&conf)
             {
                 eprintln!("{e}");
                 return Err(1);
@@ -136,15 +210,42 @@ fn  _main() -> Result<(), i32> {
         }
         Some("test") =>
         {
-            if let Err(e) = cmds::test(&mut conf)
+            let options = match TestOptions::parse(args.get(2..).unwrap_or(&[]))
             {
-                eprintln!("{e}");
+                Ok(x) => x,
+                Err(e) =>
+                {
+                    eprintln!("{RED}[TESTRUNNER]{RESET} {e}");
+                    return Err(1);
+                }
+            };
+            match cmds::test(&mut conf, &options)
+            {
+                Ok(summary) if summary.success() => (),
+                Ok(_) => return Err(EXIT_TESTS_FAILED),
+                Err(e) =>
+                {
+                    eprintln!("{e}");
+                    return Err(1);
+                }
+            }
+        }
+        Some(WORKER_CMD) =>
+        {
+            let (Some(out), Some(class)) = (args.get(2), args.get(3)) else
+            {
+                eprintln!("{RED}[TESTRUNNER]{RESET} Usage: lvjb {WORKER_CMD} <out> <class> [method]");
+                return Err(1);
+            };
+            if let Err(e) = testrunner::run_worker(Path::new(out), class, args.get(4).map(String::as_str), &mut conf)
+            {
+                eprintln!("{RED}[TESTRUNNER]{RESET} {e}");
                 return Err(1);
             }
         }
         Some("clean") =>
         {
-            if let Err(e) = cmds::clean(&conf)
+            if let Err(e) = cmds::clean(args.get(2), &mut conf)
             {
                 eprintln!("{e}");
                 return Err(1);
@@ -158,6 +259,32 @@ fn  _main() -> Result<(), i32> {
                 return Err(1);
             }
         }
+        Some("watch") =>
+        {
+            let cmd = args.get(2).map(String::as_str).unwrap_or("build");
+            let extra = args.get(3..).unwrap_or(&[]);
+            if let Err(e) = watch::watch(cmd, extra, dispatch)
+            {
+                eprintln!("{e}");
+                return Err(1);
+            }
+        }
+        Some("daemon") =>
+        {
+            let res = match args.get(2).map(String::as_str)
+            {
+                None => daemon::start(),
+                Some("--foreground") => daemon::serve(&conf, dispatch),
+                Some("stop") => daemon::stop(),
+                Some("status") => daemon::status(),
+                Some(x) => Err(format!("{RED}[DAEMON]{RESET} Unknown subcommand '{x}'").into()),
+            };
+            if let Err(e) = res
+            {
+                eprintln!("{e}");
+                return Err(1);
+            }
+        }
         Some(cmd) =>
         {
             eprintln!("{RED}[lvjb]{RESET} Unrecognized command: '{}'", cmd);
@@ -0,0 +1,612 @@
+use crate::artifacts::*;
+use crate::config::*;
+use crate::fs::*;
+use crate::pom::*;
+use crate::spawn::{GREEN, ORANGE, RED, RESET};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Maven Central, the default `repository`.
+pub const MAVEN_CENTRAL: &str = "https://repo.maven.apache.org/maven2";
+
+/// A jar in a Maven repository, from a `"group:artifact" = "version"` entry
+/// of `[dependencies]`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Artifact
+{
+    pub group:      String,
+    pub artifact:   String,
+    pub version:    String,
+}
+
+impl Artifact
+{
+    pub fn  parse(key: &str, version: &str) -> Result<Self, String>
+    {
+        match key.split(':').collect::<Vec<_>>().as_slice()
+        {
+            [group, artifact] if !group.is_empty() && !artifact.is_empty() && !version.is_empty() => Ok(Artifact
+            {
+                group:      group.to_string(),
+                artifact:   artifact.to_string(),
+                version:    version.to_string(),
+            }),
+            _ => Err(format!("{RED}[FETCH]{RESET} Invalid dependency \"{key}\" = \"{version}\", expected \"group:artifact\" = \"version\"")),
+        }
+    }
+
+    /// Name of the jar, also its name in `paths.lib`.
+    #[inline(always)]
+    pub fn  file_name(&self) -> String
+    {
+        format!("{}-{}.jar", self.artifact, self.version)
+    }
+
+    /// Path of the jar in the Maven repository layout, or of the file with
+    /// extension `ext` next to it.
+    #[inline(always)]
+    pub fn  path(&self, ext: &str) -> String
+    {
+        format!("{}/{}/{}/{}-{}.{ext}", self.group.replace('.', "/"), self.artifact, self.version, self.artifact, self.version)
+    }
+
+    #[inline(always)]
+    pub fn  url(&self, repository: &str, ext: &str) -> String
+    {
+        format!("{}/{}", repository.trim_end_matches('/'), self.path(ext))
+    }
+
+    /// `group:artifact`, the key of the resolved tree.
+    #[inline(always)]
+    pub fn  key(&self) -> String
+    {
+        format!("{}:{}", self.group, self.artifact)
+    }
+}
+
+impl fmt::Display for Artifact
+{
+    fn  fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "{}:{}:{}", self.group, self.artifact, self.version)
+    }
+}
+
+/// The `[dependencies]` of `lvjb.toml`, sorted.
+pub fn  declared(config: &Config) -> Result<Vec<Artifact>, String>
+{
+    config.dependencies.iter().map(|(key, version)| Artifact::parse(key, version)).collect()
+}
+
+/// Contents of `url`, either `http(s)://` or a `file://` path, so a local
+/// mirror can stand in for a repository.
+pub fn  download(url: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>>
+{
+    if let Some(path) = url.strip_prefix("file://")
+    {
+        return Ok(fs::read(path)?);
+    }
+    let response = reqwest::blocking::get(url)?.error_for_status()?;
+    Ok(response.bytes()?.to_vec())
+}
+
+#[inline(always)]
+fn  hex(bytes: &[u8]) -> String
+{
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// `sha256:<hex>` of `bytes`, as hashes are recorded in `lvjb.lock`.
+#[inline(always)]
+pub fn  sha256(bytes: &[u8]) -> String
+{
+    format!("sha256:{}", hex(&Sha256::digest(bytes)))
+}
+
+/// Whether `bytes` hash to `expected`, a `sha256:<hex>` or `sha1:<hex>`.
+pub fn  hash_matches(bytes: &[u8], expected: &str) -> Result<bool, String>
+{
+    let actual = match expected.split_once(':')
+    {
+        Some(("sha256", _)) => sha256(bytes),
+        Some(("sha1", _)) => format!("sha1:{}", hex(&Sha1::digest(bytes))),
+        _ => return Err(format!("Unknown checksum '{expected}', expected sha256:<hex> or sha1:<hex>")),
+    };
+    Ok(actual.eq_ignore_ascii_case(expected))
+}
+
+/// Hash this project expects for `file`, and where it comes from: pinned
+/// under `[checksums]`, or recorded in `lvjb.lock` by an earlier download.
+fn  local_hash(file: &str, config: &Config) -> Option<(String, &'static str)>
+{
+    if let Some(pinned) = config.checksums.get(file)
+    {
+        return Some((pinned.clone(), "pinned in lvjb.toml"));
+    }
+    config.cache.libs.get(file).map(|recorded| (recorded.clone(), "recorded in lvjb.lock"))
+}
+
+/// Hash the repository publishes next to `url` as `.sha256` or `.sha1`, for
+/// `file`. Only a missing sidecar means none is published; any other
+/// failure is an error.
+fn  published_hash(url: &str, file: &str) -> Result<Option<(String, &'static str)>, Box<dyn std::error::Error>>
+{
+    for (kind, len) in [("sha256", 64), ("sha1", 40)]
+    {
+        let sidecar = match download(&format!("{url}.{kind}"))
+        {
+            Ok(bytes) => bytes,
+            Err(e) if not_found(&*e) => continue,
+            Err(e) => return Err(format!("Couldn't fetch the {kind} of {file}: {e}").into()),
+        };
+        // Some repositories append the file name to the hash.
+        let text = String::from_utf8_lossy(&sidecar);
+        let digest = text.split_whitespace().next().unwrap_or_default();
+        if digest.len() != len || !digest.chars().all(|c| c.is_ascii_hexdigit())
+        {
+            return Err(format!("{url}.{kind} doesn't hold a {kind} digest, refusing {file}").into());
+        }
+        return Ok(Some((format!("{kind}:{digest}"), "published")));
+    }
+    Ok(None)
+}
+
+/// Downloads `url`, saved as `file` in `paths.lib`, and checks it against
+/// its expected hash. Returns the contents and their `sha256`.
+pub fn  download_verified(url: &str, file: &str, config: &Config) -> Result<(Vec<u8>, String), Box<dyn std::error::Error>>
+{
+    let bytes = download(url)?;
+    let expected = match local_hash(file, config)
+    {
+        Some(local) => Some(local),
+        None => published_hash(url, file)?,
+    };
+    match expected
+    {
+        Some((expected, source)) if !hash_matches(&bytes, &expected)? =>
+            return Err(format!("{file} doesn't match {expected} {source}, refusing it").into()),
+        Some(_) => (),
+        None => eprintln!("{ORANGE}[VERIFY]{RESET} No checksum published or pinned for {file}, trusting it as downloaded"),
+    }
+    let hash = sha256(&bytes);
+    Ok((bytes, hash))
+}
+
+/// Checks every jar of `paths.lib` recorded in `lvjb.lock` still has the
+/// hash it was verified with, and jars put there by hand their pinned hash.
+pub fn  verify_libs(config: &Config) -> Result<(), Box<dyn std::error::Error>>
+{
+    let pinned = config.checksums.iter().filter(|(file, _)| !config.cache.libs.contains_key(*file));
+    let mut changed = Vec::new();
+    for (file, hash) in config.cache.libs.iter().chain(pinned)
+    {
+        let Ok(bytes) = fs::read(forge_sys_path(file, config, PathType::LIB)) else { continue };
+        if !hash_matches(&bytes, hash).map_err(|e| format!("{RED}[VERIFY]{RESET} {file}: {e}"))?
+        {
+            changed.push(file.as_str());
+        }
+    }
+    if changed.is_empty()
+    {
+        return Ok(());
+    }
+    for file in &changed
+    {
+        eprintln!("{RED}[VERIFY]{RESET} {}/{file} doesn't match its recorded hash", config.paths.lib);
+    }
+    Err(format!("{RED}[VERIFY]{RESET} Refusing to build with {} modified jar(s), fetch them again or remove them", changed.len()).into())
+}
+
+/// An artifact `--offline` kept lvjb from downloading.
+#[derive(Debug)]
+pub struct Offline(pub String);
+
+impl fmt::Display for Offline
+{
+    fn  fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "{} isn't in the artifact cache, and --offline keeps lvjb off the network", self.0)
+    }
+}
+
+impl std::error::Error for Offline {}
+
+/// Whether `e` says the repository doesn't have the file.
+#[inline(always)]
+fn  not_found(e: &(dyn std::error::Error + 'static)) -> bool
+{
+    e.downcast_ref::<reqwest::Error>().and_then(reqwest::Error::status).is_some_and(|s| s.as_u16() == 404 || s.as_u16() == 410)
+        || e.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+}
+
+/// Verified contents of `url`, saved as `file`, and their `sha256`: from
+/// the shared artifact cache if it has them, else downloaded into it. Only
+/// `file://` repositories are read with `config.offline`.
+pub fn  fetch_artifact(url: &str, file: &str, config: &Config) -> Result<(Vec<u8>, String), Box<dyn std::error::Error>>
+{
+    let known = config.checksums.get(file).or(config.cache.libs.get(file)).filter(|h| h.starts_with("sha256:")).map(|h| h.to_ascii_lowercase());
+    if let Some(hash) = known.or_else(|| indexed(url))
+        && let Some(bytes) = read(&hash)
+    {
+        // What the cache holds was verified when it was stored, but this
+        // project may pin another hash.
+        match local_hash(file, config)
+        {
+            Some((expected, source)) if !hash_matches(&bytes, &expected)? =>
+                return Err(format!("{file} in the artifact cache doesn't match {expected} {source}, refusing it").into()),
+            _ =>
+            {
+                let _ = store(url, &bytes, &hash);
+                return Ok((bytes, hash));
+            }
+        }
+    }
+    if config.offline && !url.starts_with("file://")
+    {
+        return match indexed(url).as_deref()
+        {
+            Some(MISSING) => Err(Box::new(std::io::Error::new(std::io::ErrorKind::NotFound, format!("{url} wasn't found when last fetched")))),
+            _ => Err(Box::new(Offline(file.to_string()))),
+        };
+    }
+    eprintln!("{ORANGE}[FETCHING]{RESET} {url}");
+    let (bytes, hash) = match download_verified(url, file, config)
+    {
+        Err(e) if not_found(&*e) =>
+        {
+            let _ = mark_missing(url);
+            return Err(e);
+        }
+        x => x?,
+    };
+    if let Err(e) = store(url, &bytes, &hash)
+    {
+        eprintln!("{ORANGE}[CACHE]{RESET} Couldn't add {file} to the artifact cache: {e}");
+    }
+    Ok((bytes, hash))
+}
+
+/// Puts the verified contents of `url` at `dest`, hardlinked from the
+/// artifact cache where possible, and returns their `sha256`.
+pub fn  install(url: &str, file: &str, dest: &Path, config: &Config) -> Result<String, Box<dyn std::error::Error>>
+{
+    let (bytes, hash) = fetch_artifact(url, file, config)?;
+    if !link(&hash, dest)
+    {
+        write_atomic(dest, &bytes)?;
+    }
+    Ok(hash)
+}
+
+/// A jar of the resolved dependency tree, as recorded in `lvjb.lock` under
+/// its `group:artifact`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Resolved
+{
+    pub version:        String,
+    /// Steps from `[dependencies]`, 0 for the declared jars.
+    pub depth:          usize,
+    /// `group:artifact:version` of the jar whose POM pulled this one in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub required_by:    Option<String>,
+    /// `group:artifact:version` of what its POM asks for, before conflicts
+    /// were settled.
+    #[serde(default)]
+    pub dependencies:   Vec<String>,
+}
+
+pub type Tree = BTreeMap<String, Resolved>;
+
+/// Settles a version range such as `[1.2,2.0)` on its lower bound, Maven
+/// would need the repository's version list for better. `None` for a range
+/// without an inclusive lower bound, such as `(1.0,2.0)` or `(,2.0]`.
+fn  pick_version(version: &str) -> Option<String>
+{
+    if !version.starts_with(['[', '('])
+    {
+        return Some(version.to_string());
+    }
+    let lower = version[1..].split(',').next()?.trim_end_matches([']', ')']).trim();
+    (version.starts_with('[') && !lower.is_empty()).then(|| lower.to_string())
+}
+
+/// Whether `key` (`group:artifact`) matches one of the exclusion patterns.
+#[inline(always)]
+fn  excluded(key: &str, exclusions: &[String]) -> bool
+{
+    exclusions.iter().any(|x| x.split(':').zip(key.split(':')).all(|(p, k)| p == "*" || p == k))
+}
+
+/// Reads the POMs of the tree, each once.
+struct Resolver<'a>
+{
+    config: &'a Config,
+    poms:   HashMap<String, Option<Pom>>,
+}
+
+impl Resolver<'_>
+{
+    /// The POM of `a` as published. A missing or unparsable one leaves `a`
+    /// without dependencies, as it does in Maven; failing to download it
+    /// fails, so an incomplete tree isn't recorded.
+    fn  raw(&mut self, a: &Artifact) -> Result<Option<Pom>, Box<dyn std::error::Error>>
+    {
+        if let Some(pom) = self.poms.get(&a.to_string())
+        {
+            return Ok(pom.clone());
+        }
+        let file = format!("{}-{}.pom", a.artifact, a.version);
+        let pom = match fetch_artifact(&a.url(&self.config.repository, "pom"), &file, self.config)
+        {
+            Ok((bytes, _)) => match Pom::parse(&String::from_utf8_lossy(&bytes))
+            {
+                Ok(pom) => Some(pom),
+                Err(e) =>
+                {
+                    eprintln!("{ORANGE}[RESOLVE]{RESET} Broken POM for {a} ({e}), taking it without dependencies");
+                    None
+                }
+            },
+            Err(e) if not_found(&*e) =>
+            {
+                eprintln!("{ORANGE}[RESOLVE]{RESET} No POM for {a}, taking it without dependencies");
+                None
+            }
+            Err(e) => return Err(e),
+        };
+        self.poms.insert(a.to_string(), pom.clone());
+        Ok(pom)
+    }
+
+    /// The POM of `a` with its parents' settings, not yet interpolated.
+    fn  merged(&mut self, a: &Artifact, depth: usize) -> Result<Option<Pom>, Box<dyn std::error::Error>>
+    {
+        let Some(pom) = self.raw(a)? else { return Ok(None) };
+        let parent = match &pom.parent
+        {
+            Some((group, artifact, version)) if depth < 16 => Artifact::parse(&format!("{group}:{artifact}"), version).ok(),
+            _ => None,
+        };
+        let parent = match parent
+        {
+            Some(p) => self.merged(&p, depth + 1)?,
+            None => None,
+        };
+        Ok(Some(match parent
+        {
+            Some(p) => pom.inherit(&p),
+            None => pom,
+        }))
+    }
+
+    /// The POM of `a` as Maven builds it: inherited, interpolated, with the
+    /// imported BOMs' and its own `<dependencyManagement>` applied.
+    fn  effective(&mut self, a: &Artifact, depth: usize) -> Result<Option<Pom>, Box<dyn std::error::Error>>
+    {
+        let Some(mut pom) = self.merged(a, depth)? else { return Ok(None) };
+        pom.interpolate();
+        let (imports, mut managed): (Vec<_>, Vec<_>) = pom.managed.drain(..).partition(|m| m.scope.as_deref() == Some("import"));
+        for bom in imports.iter().filter(|_| depth < 16)
+        {
+            let Some(bom) = bom.version.as_ref().and_then(|v| Artifact::parse(&bom.key(), v).ok()) else { continue };
+            if let Some(imported) = self.effective(&bom, depth + 1)?
+            {
+                managed.extend(imported.managed);
+            }
+        }
+        pom.managed = managed;
+        pom.apply_managed();
+        Ok(Some(pom))
+    }
+}
+
+/// Whether the tree in `lvjb.lock` was resolved from the current
+/// `[dependencies]`.
+fn  tree_current(config: &Config) -> bool
+{
+    let roots: BTreeMap<&String, &String> = config.cache.tree.iter()
+        .filter(|(_, r)| r.depth == 0)
+        .map(|(key, r)| (key, &r.version))
+        .collect();
+    roots.len() == config.dependencies.len() && config.dependencies.iter().all(|(k, v)| roots.get(k) == Some(&v))
+}
+
+/// Every jar `[dependencies]` need: the declared ones, and what their POMs
+/// ask for with compile or runtime scope, transitively. When two versions of
+/// a jar are asked for, the one nearest to `[dependencies]` wins, and the
+/// first asked for among equally near ones; the declared versions always
+/// win. Exclusions are honoured; optional, test and provided dependencies
+/// are left out. The tree in `lvjb.lock` is reused while `[dependencies]`
+/// are unchanged.
+pub fn  resolve(config: &Config) -> Result<Tree, Box<dyn std::error::Error>>
+{
+    let declared = declared(config)?;
+    if tree_current(config)
+    {
+        return Ok(config.cache.tree.clone());
+    }
+    eprintln!("{ORANGE}[RESOLVE]{RESET} Resolving {} dependencies from {}", declared.len(), config.repository);
+    let mut resolver = Resolver { config, poms: HashMap::new() };
+    let mut queue: VecDeque<(Artifact, usize, Option<String>, Vec<String>)> = declared.into_iter().map(|a| (a, 0, None, Vec::new())).collect();
+    let mut tree = Tree::new();
+    while let Some((a, depth, required_by, exclusions)) = queue.pop_front()
+    {
+        if tree.contains_key(&a.key())
+        {
+            continue;
+        }
+        let pom = resolver.effective(&a, 0).map_err(|e| format!("{RED}[RESOLVE]{RESET} {a}: {e}"))?.unwrap_or_default();
+        let mut dependencies = Vec::new();
+        for d in &pom.dependencies
+        {
+            let wanted = matches!(d.scope.as_deref(), None | Some("compile") | Some("runtime"))
+                && matches!(d.kind.as_deref(), None | Some("jar") | Some("bundle"))
+                && d.classifier.is_none()
+                && !d.optional
+                && !excluded(&d.key(), &exclusions);
+            if !wanted
+            {
+                continue;
+            }
+            let Some(version) = &d.version else
+            {
+                eprintln!("{ORANGE}[RESOLVE]{RESET} {a} asks for {} without a version, and none is managed, skipped", d.key());
+                continue;
+            };
+            let Some(picked) = pick_version(version) else
+            {
+                eprintln!("{ORANGE}[RESOLVE]{RESET} {a} asks for {} {version}, a range without an inclusive lower bound, skipped", d.key());
+                continue;
+            };
+            let Ok(dep) = Artifact::parse(&d.key(), &picked) else { continue };
+            if dep.to_string().contains("${")
+            {
+                eprintln!("{ORANGE}[RESOLVE]{RESET} {a} asks for {dep}, which has an unknown property, skipped");
+                continue;
+            }
+            dependencies.push(dep.to_string());
+            let inherited = exclusions.iter().chain(&d.exclusions).cloned().collect();
+            queue.push_back((dep, depth + 1, Some(a.to_string()), inherited));
+        }
+        tree.insert(a.key(), Resolved { version: a.version.clone(), depth, required_by, dependencies });
+    }
+    Ok(tree)
+}
+
+/// The jars of `tree`.
+pub fn  tree_artifacts(tree: &Tree) -> Vec<Artifact>
+{
+    tree.iter().filter_map(|(key, r)| Artifact::parse(key, &r.version).ok()).collect()
+}
+
+/// Prints the dependencies `gav` asks for below it, and why each is or
+/// isn't in the tree.
+fn  print_children(tree: &Tree, gav: &str, prefix: &str)
+{
+    let Some((key, _)) = gav.rsplit_once(':') else { return };
+    let Some(node) = tree.get(key) else { return };
+    for (i, child) in node.dependencies.iter().enumerate()
+    {
+        let last = i + 1 == node.dependencies.len();
+        let branch = if last { "└── " } else { "├── " };
+        let Some((child_key, version)) = child.rsplit_once(':') else { continue };
+        let Some(resolved) = tree.get(child_key) else { continue };
+        let winner = match &resolved.required_by
+        {
+            Some(by) => format!("required by {by}"),
+            None => "declared".to_string(),
+        };
+        if resolved.version != version
+        {
+            println!("{prefix}{branch}{child} {ORANGE}(omitted for conflict with {}, nearer: {winner}){RESET}", resolved.version);
+        }
+        else if resolved.required_by.as_deref() != Some(gav)
+        {
+            println!("{prefix}{branch}{child} {ORANGE}(omitted as duplicate, already {winner}){RESET}");
+        }
+        else
+        {
+            println!("{prefix}{branch}{child} (required by {gav})");
+            print_children(tree, child, &format!("{prefix}{}", if last { "    " } else { "│   " }));
+        }
+    }
+}
+
+/// `lvjb tree`: resolves `[dependencies]` and prints the tree, with the
+/// reason each jar is in it.
+pub fn  tree(config: &mut Config) -> Result<(), Box<dyn std::error::Error>>
+{
+    let tree = resolve(config)?;
+    if tree != config.cache.tree
+    {
+        config.cache.tree = tree.clone();
+        config.cache.write()?;
+    }
+    for (key, r) in tree.iter().filter(|(_, r)| r.depth == 0)
+    {
+        let gav = format!("{key}:{}", r.version);
+        println!("{gav} (declared)");
+        print_children(&tree, &gav, "");
+    }
+    let declared = tree.values().filter(|r| r.depth == 0).count();
+    eprintln!("{GREEN}[TREE]{RESET} {} jar(s), {declared} declared and {} pulled in by them", tree.len(), tree.len() - declared);
+    Ok(())
+}
+
+/// `lvjb fetch`: resolves `[dependencies]`, installs the jars of the tree
+/// missing from `paths.lib` and deletes the jars of earlier fetches no
+/// longer in it. Other jars in `paths.lib` are left alone.
+pub fn  fetch(config: &mut Config) -> Result<(), Box<dyn std::error::Error>>
+{
+    let tree = resolve(config)?;
+    let wanted = tree_artifacts(&tree);
+    config.cache.tree = tree;
+    let mut names = BTreeSet::new();
+    if let Some(clash) = wanted.iter().map(Artifact::file_name).find(|f| !names.insert(f.clone()))
+    {
+        return Err(format!("{RED}[FETCH]{RESET} Two dependencies would both be {}/{clash}", config.paths.lib).into());
+    }
+    fs::create_dir_all(&config.paths.lib)?;
+
+    let mut removed = 0;
+    for (file, artifact) in config.cache.fetched.clone()
+    {
+        if names.contains(&file)
+        {
+            continue;
+        }
+        match fs::remove_file(forge_sys_path(&file, config, PathType::LIB))
+        {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(format!("{RED}[FETCH]{RESET} Couldn't remove {file}: {e}").into()),
+            _ => eprintln!("{ORANGE}[FETCH]{RESET} Removed {file} ({artifact} is no longer a dependency)"),
+        }
+        config.cache.fetched.remove(&file);
+        config.cache.libs.remove(&file);
+        removed += 1;
+    }
+
+    let mut added = 0;
+    let mut failed = Vec::new();
+    for a in &wanted
+    {
+        let file = a.file_name();
+        let dest = forge_sys_path(&file, config, PathType::LIB);
+        // A jar modified since it was verified is fetched again.
+        if dest.exists() && config.cache.libs.get(&file).is_none_or(|h| fs::read(&dest).is_ok_and(|b| sha256(&b) == *h))
+        {
+            continue;
+        }
+        let url = a.url(&config.repository, "jar");
+        match install(&url, &file, &dest, config)
+        {
+            Ok(hash) =>
+            {
+                config.cache.libs.insert(file.clone(), hash);
+                config.cache.fetched.insert(file, a.to_string());
+                added += 1;
+            }
+            Err(e) if e.is::<Offline>() =>
+            {
+                config.cache.write()?;
+                return Err(format!("{RED}[FETCH]{RESET} {a}: {e}").into());
+            }
+            Err(e) =>
+            {
+                eprintln!("{RED}[FETCH]{RESET} {a}: {e}");
+                failed.push(a.to_string());
+            }
+        }
+    }
+    config.cache.write()?;
+    if !failed.is_empty()
+    {
+        return Err(format!("{RED}[FETCH]{RESET} Couldn't fetch {}", failed.join(", ")).into());
+    }
+    eprintln!("{GREEN}[FETCHED]{RESET} {} jar(s) in {}: {added} added, {removed} removed", wanted.len(), config.paths.lib);
+    Ok(())
+}
@@ -0,0 +1,232 @@
+use crate::config::*;
+use crate::fs::*;
+use crate::diagnostics::*;
+use crate::incremental::record_dependencies;
+use crate::spawn::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+/// Sources that must go through the same javac call (one package, or a
+/// package cycle), and the units that have to be compiled before them.
+pub struct CompilationUnit
+{
+    pub files:      Vec<PathBuf>,
+    pub after:      HashSet<usize>,
+}
+
+#[inline(always)]
+pub fn  job_count(config: &Config) -> usize
+{
+    match config.jobs
+    {
+        0 => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        n => n,
+    }
+}
+
+#[inline(always)]
+fn  reachable(from: &Path, edges: &HashMap<PathBuf, HashSet<PathBuf>>) -> HashSet<PathBuf>
+{
+    let mut seen = HashSet::new();
+    let mut stack = vec![from.to_path_buf()];
+    while let Some(p) = stack.pop()
+    {
+        for next in edges.get(&p).into_iter().flatten()
+        {
+            if seen.insert(next.clone())
+            {
+                stack.push(next.clone());
+            }
+        }
+    }
+    seen
+}
+
+/// Splits `files` by package and orders the packages with the dependency
+/// graph from the last build. Returns `None` unless `parallel_javac` is on,
+/// when there is nothing to gain from running more than one javac, and when
+/// there is no graph yet to order them with.
+pub fn  compilation_units(files: &[PathBuf], config: &Config) -> Option<Vec<CompilationUnit>>
+{
+    if !config.parallel_javac || job_count(config) < 2 || config.cache.deps.is_empty()
+    {
+        return None;
+    }
+    let mut packages: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for file in files
+    {
+        let pkg = source_package(file, config)
+            .unwrap_or_else(|| file.parent().map(PathBuf::from).unwrap_or_default());
+        packages.entry(pkg).or_default().push(file.clone());
+    }
+    if packages.len() < 2
+    {
+        return None;
+    }
+
+    let mut edges: HashMap<PathBuf, HashSet<PathBuf>> = HashMap::new();
+    for (pkg, members) in &packages
+    {
+        for file in members
+        {
+            for dep in config.cache.deps.get(file.to_string_lossy().as_ref()).into_iter().flatten()
+            {
+                if let Some(dep_pkg) = source_package(&PathBuf::from(dep), config)
+                    && dep_pkg != *pkg
+                    && packages.contains_key(&dep_pkg)
+                {
+                    edges.entry(pkg.clone()).or_default().insert(dep_pkg);
+                }
+            }
+        }
+    }
+
+    // Packages that reach each other form a cycle and share one javac call.
+    let reach: HashMap<PathBuf, HashSet<PathBuf>> = packages.keys()
+        .map(|p| (p.clone(), reachable(p, &edges)))
+        .collect();
+    let mut unit_of: HashMap<PathBuf, usize> = HashMap::new();
+    let mut units: Vec<CompilationUnit> = Vec::new();
+    for pkg in packages.keys()
+    {
+        if unit_of.contains_key(pkg)
+        {
+            continue;
+        }
+        let id = units.len();
+        let mut unit = CompilationUnit { files: Vec::new(), after: HashSet::new() };
+        for other in packages.keys()
+        {
+            if other == pkg || (reach[pkg].contains(other) && reach[other].contains(pkg))
+            {
+                unit_of.insert(other.clone(), id);
+                unit.files.extend(packages[other].iter().cloned());
+            }
+        }
+        units.push(unit);
+    }
+    for (pkg, deps) in &edges
+    {
+        let id = unit_of[pkg];
+        for dep in deps
+        {
+            if unit_of[dep] != id
+            {
+                units[id].after.insert(unit_of[dep]);
+            }
+        }
+    }
+    Some(units)
+}
+
+/// Whether the classes just compiled reference a unit that wasn't finished
+/// before theirs started: an edit added a reference the graph from the last
+/// build doesn't know, so a unit may have compiled against stale classes.
+fn  misordered(units: &[CompilationUnit], batch_of: &HashMap<usize, usize>, config: &Config) -> bool
+{
+    let unit_of: HashMap<String, usize> = units.iter().enumerate()
+        .flat_map(|(i, u)| u.files.iter().map(move |f| (f.to_string_lossy().into_owned(), i)))
+        .collect();
+    let mut fresh = config.clone();
+    record_dependencies(&mut fresh);
+    unit_of.iter().any(|(file, i)| fresh.cache.deps.get(file).into_iter().flatten()
+        .filter_map(|dep| unit_of.get(dep))
+        .any(|j| j != i && batch_of[j] >= batch_of[i]))
+}
+
+/// Compiles `units` in dependency order with up to `jobs` javac processes at
+/// once. If a unit fails (possibly because the graph from the last build is
+/// stale), everything not yet compiled goes through a single javac call so
+/// the diagnostics are the real ones. If they all compile but now depend on
+/// each other in an order the graph didn't give, all of them do.
+pub fn  compile_parallel(units: Vec<CompilationUnit>, config: &Config, classpath: &str) -> Result<CompileResult, Box<dyn std::error::Error>>
+{
+    let json = config.message_format == MESSAGE_FORMAT_JSON;
+    let jobs = job_count(config);
+    let mut classpath = classpath.to_string();
+    if !classpath.split(':').any(|x| x == config.paths.bin)
+    {
+        classpath = if classpath.is_empty() { config.paths.bin.clone() } else { format!("{classpath}:{}", config.paths.bin) };
+    }
+    eprintln!("{ORANGE}[COMPILER]{RESET} {} compilation unit(s), {jobs} job(s)", units.len());
+
+    let mut done: HashSet<usize> = HashSet::new();
+    // Batch each unit was compiled in, batches run one after the other.
+    let mut batch_of: HashMap<usize, usize> = HashMap::new();
+    let mut batches = 0;
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    let mut failed = false;
+    while !failed && done.len() < units.len()
+    {
+        let ready: Vec<usize> = (0..units.len())
+            .filter(|i| !done.contains(i) && units[*i].after.iter().all(|d| done.contains(d)))
+            .collect();
+        if ready.is_empty()
+        {
+            break;
+        }
+        for batch in ready.chunks(jobs)
+        {
+            let results: Vec<(usize, Result<JavacOutput, String>)> = thread::scope(|s|
+            {
+                let handles: Vec<_> = batch.iter()
+                    .map(|i|
+                    {
+                        let files = &units[*i].files;
+                        let cp = &classpath;
+                        (*i, s.spawn(move || run_javac(files, config, cp).map_err(|e| e.to_string())))
+                    })
+                    .collect();
+                handles.into_iter()
+                    .map(|(i, h)| (i, h.join().unwrap_or_else(|_| Err("javac worker panicked".to_string()))))
+                    .collect()
+            });
+            for (i, result) in results
+            {
+                match result
+                {
+                    Ok(out) if out.status.success() =>
+                    {
+                        if !json
+                        {
+                            eprint!("{}", out.stderr);
+                        }
+                        diagnostics.extend(parse_javac(&out.stderr));
+                        done.insert(i);
+                        batch_of.insert(i, batches);
+                    }
+                    Ok(_) => failed = true,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            if failed
+            {
+                break;
+            }
+            batches += 1;
+        }
+    }
+    let rest: Vec<PathBuf> = match done.len() == units.len()
+    {
+        true if !misordered(&units, &batch_of, config) => return Ok((true, diagnostics, None)),
+        true =>
+        {
+            eprintln!("{ORANGE}[COMPILER]{RESET} Packages now depend on each other in an order the last build didn't know");
+            diagnostics.clear();
+            units.iter().flat_map(|u| u.files.iter().cloned()).collect()
+        }
+        false => (0..units.len())
+            .filter(|i| !done.contains(i))
+            .flat_map(|i| units[i].files.iter().cloned())
+            .collect(),
+    };
+    eprintln!("{ORANGE}[COMPILER]{RESET} Falling back to a single javac run for {} file(s)", rest.len());
+    let out = run_javac(&rest, config, &classpath)?;
+    if !json
+    {
+        eprint!("{}", out.stderr);
+    }
+    diagnostics.extend(parse_javac(&out.stderr));
+    Ok((out.status.success(), diagnostics, Some(out.status)))
+}
@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+
+/// An XML element: its name without namespace prefix, its text and the
+/// elements it contains. Attributes are dropped, POMs don't use them.
+#[derive(Debug, Clone, Default)]
+pub struct Element
+{
+    pub name:       String,
+    pub text:       String,
+    pub children:   Vec<Element>,
+}
+
+impl Element
+{
+    #[inline(always)]
+    pub fn  child(&self, name: &str) -> Option<&Element>
+    {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    /// Trimmed text of the child `name`, if there is one with any.
+    #[inline(always)]
+    pub fn  text_of(&self, name: &str) -> Option<String>
+    {
+        self.child(name).map(|c| c.text.trim().to_string()).filter(|t| !t.is_empty())
+    }
+
+    /// Children `item` of the child `list`, as in `<dependencies><dependency>`.
+    #[inline(always)]
+    pub fn  list(&self, list: &str, item: &str) -> Vec<&Element>
+    {
+        self.child(list).map(|l| l.children.iter().filter(|c| c.name == item).collect()).unwrap_or_default()
+    }
+}
+
+fn  unescape(s: &str) -> String
+{
+    s.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&amp;", "&")
+}
+
+/// Parses the root element of `xml`. Comments, processing instructions and
+/// the doctype are skipped.
+pub fn  parse_xml(xml: &str) -> Result<Element, String>
+{
+    let mut stack: Vec<Element> = vec![Element::default()];
+    let mut rest = xml;
+    while let Some(start) = rest.find('<')
+    {
+        if let Some(top) = stack.last_mut()
+        {
+            top.text.push_str(&unescape(&rest[..start]));
+        }
+        rest = &rest[start..];
+        let skip = |end: &str, rest: &str| rest.find(end).map(|i| i + end.len()).ok_or(format!("unterminated '{}'", &rest[..rest.len().min(16)]));
+        if let Some(cdata) = rest.strip_prefix("<![CDATA[")
+        {
+            let end = cdata.find("]]>").ok_or("unterminated CDATA")?;
+            if let Some(top) = stack.last_mut()
+            {
+                top.text.push_str(&cdata[..end]);
+            }
+            rest = &cdata[end + 3..];
+            continue;
+        }
+        let end = match rest
+        {
+            r if r.starts_with("<!--") => skip("-->", r)?,
+            r if r.starts_with("<?") => skip("?>", r)?,
+            r if r.starts_with("<!") => skip(">", r)?,
+            r =>
+            {
+                let end = skip(">", r)?;
+                let tag = &r[1..end - 1];
+                if let Some(closing) = tag.strip_prefix('/')
+                {
+                    let done = stack.pop().filter(|_| !stack.is_empty()).ok_or("unbalanced closing tag")?;
+                    if done.name != closing.trim().rsplit(':').next().unwrap_or_default()
+                    {
+                        return Err(format!("<{}> closed by </{}>", done.name, closing.trim()));
+                    }
+                    stack.last_mut().ok_or("unbalanced closing tag")?.children.push(done);
+                }
+                else
+                {
+                    let name = tag.split(|c: char| c.is_whitespace() || c == '/').next().unwrap_or_default();
+                    let element = Element { name: name.rsplit(':').next().unwrap_or_default().to_string(), ..Element::default() };
+                    match tag.ends_with('/')
+                    {
+                        true => stack.last_mut().ok_or("unbalanced tag")?.children.push(element),
+                        false => stack.push(element),
+                    }
+                }
+                end
+            }
+        };
+        rest = &rest[end..];
+    }
+    match stack.pop()
+    {
+        Some(mut document) if stack.is_empty() && document.children.len() == 1 => Ok(document.children.remove(0)),
+        _ => Err("expected a single root element".to_string()),
+    }
+}
+
+/// A `<dependency>`, in `<dependencies>` or `<dependencyManagement>`.
+#[derive(Debug, Clone, Default)]
+pub struct PomDependency
+{
+    pub group:      String,
+    pub artifact:   String,
+    pub version:    Option<String>,
+    pub scope:      Option<String>,
+    pub kind:       Option<String>,
+    pub classifier: Option<String>,
+    pub optional:   bool,
+    /// `group:artifact` patterns, `*` for any part.
+    pub exclusions: Vec<String>,
+}
+
+impl PomDependency
+{
+    fn  parse(e: &Element) -> Self
+    {
+        PomDependency
+        {
+            group:      e.text_of("groupId").unwrap_or_default(),
+            artifact:   e.text_of("artifactId").unwrap_or_default(),
+            version:    e.text_of("version"),
+            scope:      e.text_of("scope"),
+            kind:       e.text_of("type"),
+            classifier: e.text_of("classifier"),
+            optional:   e.text_of("optional").is_some_and(|o| o == "true"),
+            exclusions: e.list("exclusions", "exclusion").into_iter()
+                .map(|x| format!("{}:{}", x.text_of("groupId").unwrap_or("*".into()), x.text_of("artifactId").unwrap_or("*".into())))
+                .collect(),
+        }
+    }
+
+    /// `group:artifact`, how Maven tells dependencies apart.
+    #[inline(always)]
+    pub fn  key(&self) -> String
+    {
+        format!("{}:{}", self.group, self.artifact)
+    }
+
+    /// Replaces `${...}` in every field with `properties`.
+    fn  interpolate(&mut self, properties: &HashMap<String, String>)
+    {
+        for field in [&mut self.group, &mut self.artifact]
+        {
+            *field = interpolate(field, properties);
+        }
+        for field in [&mut self.version, &mut self.scope, &mut self.kind, &mut self.classifier].into_iter().flatten()
+        {
+            *field = interpolate(field, properties);
+        }
+    }
+}
+
+/// A POM as written, before inheritance and interpolation.
+#[derive(Debug, Clone, Default)]
+pub struct Pom
+{
+    pub group:          Option<String>,
+    pub artifact:       String,
+    pub version:        Option<String>,
+    /// `group`, `artifact` and `version` of the parent POM.
+    pub parent:         Option<(String, String, String)>,
+    pub properties:     HashMap<String, String>,
+    pub managed:        Vec<PomDependency>,
+    pub dependencies:   Vec<PomDependency>,
+}
+
+impl Pom
+{
+    pub fn  parse(xml: &str) -> Result<Self, String>
+    {
+        let project = parse_xml(xml)?;
+        if project.name != "project"
+        {
+            return Err(format!("expected <project>, found <{}>", project.name));
+        }
+        let parent = project.child("parent").and_then(|p| Some((p.text_of("groupId")?, p.text_of("artifactId")?, p.text_of("version")?)));
+        let dependencies = |e: Option<&Element>| e.map(|e| e.list("dependencies", "dependency").into_iter().map(PomDependency::parse).collect()).unwrap_or_default();
+        Ok(Pom
+        {
+            group:          project.text_of("groupId"),
+            artifact:       project.text_of("artifactId").unwrap_or_default(),
+            version:        project.text_of("version"),
+            parent,
+            properties:     project.child("properties").map(|p| p.children.iter().map(|c| (c.name.clone(), c.text.trim().to_string())).collect()).unwrap_or_default(),
+            managed:        dependencies(project.child("dependencyManagement")),
+            dependencies:   dependencies(Some(&project)),
+        })
+    }
+
+    /// This POM with `parent`'s settings it doesn't override itself.
+    pub fn  inherit(mut self, parent: &Pom) -> Self
+    {
+        self.group = self.group.or(parent.group.clone());
+        self.version = self.version.or(parent.version.clone());
+        for (k, v) in &parent.properties
+        {
+            self.properties.entry(k.clone()).or_insert_with(|| v.clone());
+        }
+        self.managed.extend(parent.managed.iter().cloned());
+        let own: Vec<String> = self.dependencies.iter().map(PomDependency::key).collect();
+        self.dependencies.extend(parent.dependencies.iter().filter(|d| !own.contains(&d.key())).cloned());
+        self
+    }
+
+    /// Fills in `${...}` from the properties and the project's coordinates.
+    pub fn  interpolate(&mut self)
+    {
+        let mut properties = self.properties.clone();
+        let project = [("groupId", self.group.clone()), ("artifactId", Some(self.artifact.clone())), ("version", self.version.clone())];
+        for (name, value) in project
+        {
+            for prefix in ["project", "pom"]
+            {
+                properties.insert(format!("{prefix}.{name}"), value.clone().unwrap_or_default());
+            }
+        }
+        if let Some((g, _, v)) = &self.parent
+        {
+            properties.insert("project.parent.groupId".into(), g.clone());
+            properties.insert("project.parent.version".into(), v.clone());
+        }
+        for d in self.managed.iter_mut().chain(self.dependencies.iter_mut())
+        {
+            d.interpolate(&properties);
+        }
+    }
+
+    /// Version, scope and other settings `<dependencyManagement>` gives the
+    /// dependencies that leave them out.
+    pub fn  apply_managed(&mut self)
+    {
+        for d in &mut self.dependencies
+        {
+            let Some(m) = self.managed.iter().find(|m| m.key() == d.key() && m.classifier == d.classifier) else { continue };
+            d.version = d.version.take().or(m.version.clone());
+            d.scope = d.scope.take().or(m.scope.clone());
+            if d.exclusions.is_empty()
+            {
+                d.exclusions = m.exclusions.clone();
+            }
+        }
+    }
+}
+
+/// `text` with `${name}` replaced from `properties`, nested ones included.
+/// Unknown properties are left as they are.
+pub fn  interpolate(text: &str, properties: &HashMap<String, String>) -> String
+{
+    let mut out = text.to_string();
+    let mut from = 0;
+    // Bounded, a property could refer to itself.
+    let mut budget = 32;
+    while budget > 0
+        && let Some(i) = out[from..].find("${")
+    {
+        let start = from + i;
+        let Some(len) = out[start..].find('}') else { break };
+        match properties.get(&out[start + 2..start + len])
+        {
+            Some(value) =>
+            {
+                out = format!("{}{value}{}", &out[..start], &out[start + len + 1..]);
+                budget -= 1;
+            }
+            None => from = start + len + 1,
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn  parses_cdata_comments_and_entities()
+    {
+        let root = parse_xml("<?xml version=\"1.0\"?><!DOCTYPE x><a><!-- <b>no</b> --><b><![CDATA[1 < 2 & <c/>]]></b><c>x &amp;&lt;y&gt;</c></a>").unwrap();
+        assert_eq!(root.name, "a");
+        assert_eq!(root.children.len(), 2);
+        assert_eq!(root.text_of("b").as_deref(), Some("1 < 2 & <c/>"));
+        assert_eq!(root.text_of("c").as_deref(), Some("x &<y>"));
+    }
+
+    #[test]
+    fn  drops_namespace_prefixes_and_attributes()
+    {
+        let root = parse_xml("<p:project xmlns:p=\"http://maven.apache.org/POM/4.0.0\"><p:version a=\"b\">1</p:version ></p:project>").unwrap();
+        assert_eq!(root.name, "project");
+        assert_eq!(root.text_of("version").as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn  self_closing_tags_have_no_children()
+    {
+        let root = parse_xml("<a><b/><c x=\"1\" /><d>t</d></a>").unwrap();
+        let names: Vec<&str> = root.children.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["b", "c", "d"]);
+        assert!(root.child("b").unwrap().children.is_empty());
+        assert_eq!(root.text_of("b"), None);
+    }
+
+    #[test]
+    fn  rejects_malformed_documents()
+    {
+        assert!(parse_xml("<a><b></a>").is_err());
+        assert!(parse_xml("<a></a></b>").is_err());
+        assert!(parse_xml("<a><![CDATA[x</a>").is_err());
+        assert!(parse_xml("<a/><b/>").is_err());
+        assert!(parse_xml("<a").is_err());
+    }
+
+    #[test]
+    fn  interpolates_nested_properties()
+    {
+        let properties = HashMap::from([
+            ("major".to_string(), "2".to_string()),
+            ("version".to_string(), "${major}.${minor}".to_string()),
+            ("minor".to_string(), "1".to_string()),
+        ]);
+        assert_eq!(interpolate("v${version}-${unknown}", &properties), "v2.1-${unknown}");
+        assert_eq!(interpolate("${unterminated", &properties), "${unterminated");
+    }
+
+    #[test]
+    fn  self_referential_properties_terminate()
+    {
+        let properties = HashMap::from([
+            ("a".to_string(), "${a}".to_string()),
+            ("b".to_string(), "x${b}".to_string()),
+            ("c".to_string(), "${d}".to_string()),
+            ("d".to_string(), "${c}".to_string()),
+        ]);
+        assert_eq!(interpolate("${a}", &properties), "${a}");
+        assert!(interpolate("${b}", &properties).starts_with("xxx"));
+        assert!(interpolate("${c}", &properties).contains("${"));
+    }
+
+    #[test]
+    fn  pom_fills_in_project_coordinates()
+    {
+        let mut pom = Pom::parse("<project><parent><groupId>g</groupId><artifactId>p</artifactId><version>3</version></parent>\
+            <artifactId>a</artifactId><properties><dep.version>${project.parent.version}.1</dep.version></properties>\
+            <dependencies><dependency><groupId>${project.groupId}</groupId><artifactId>d</artifactId><version>${dep.version}</version></dependency></dependencies></project>").unwrap();
+        pom = pom.inherit(&Pom { group: Some("g".into()), version: Some("3".into()), ..Pom::default() });
+        pom.interpolate();
+        assert_eq!(pom.dependencies[0].key(), "g:d");
+        assert_eq!(pom.dependencies[0].version.as_deref(), Some("3.1"));
+    }
+}
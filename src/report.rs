@@ -0,0 +1,260 @@
+use crate::testrunner::*;
+use crate::spawn::{GREEN, RED, RESET};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// A machine-readable test report requested with `--report`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Report
+{
+    /// JUnit XML, the format CI dashboards ingest.
+    Junit(PathBuf),
+    /// TAP version 13, to a file or to stdout.
+    Tap(Option<PathBuf>),
+}
+
+impl Report
+{
+    /// Parses `junit=<path>`, `tap` or `tap=<path>`.
+    pub fn  parse(spec: &str) -> Result<Self, String>
+    {
+        let (kind, path) = match spec.split_once('=')
+        {
+            Some((k, p)) => (k, Some(PathBuf::from(p))),
+            None => (spec, None),
+        };
+        match (kind, path)
+        {
+            ("junit", Some(p)) => Ok(Report::Junit(p)),
+            ("junit", None) => Err("--report junit needs a path: junit=<path>".to_string()),
+            ("tap", p) => Ok(Report::Tap(p)),
+            _ => Err(format!("Unknown report format '{kind}', expected junit=<path> or tap[=<path>]")),
+        }
+    }
+}
+
+#[inline(always)]
+fn  seconds(d: Duration) -> String
+{
+    format!("{:.3}", d.as_secs_f64())
+}
+
+/// Escapes text for use in XML attributes and character data.
+fn  xml_escape(s: &str) -> String
+{
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars()
+    {
+        match c
+        {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\n' | '\r' | '\t' => out.push(c),
+            c if (c as u32) < 0x20 => (),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Exception class of a failure, from the `toString()` it was reported with.
+#[inline(always)]
+fn  exception_type(failure: &Failure) -> Option<&str>
+{
+    failure.trace.as_ref()?;
+    let head = failure.message.split(':').next()?;
+    head.contains('.').then_some(head).filter(|h| !h.contains(' '))
+}
+
+/// A `<failure>` or `<error>`, or with `rerun`, Surefire's `<rerunFailure>`
+/// or `<rerunError>` for an earlier attempt of a test that still failed, and
+/// `<flakyFailure>` or `<flakyError>` for one that passed later.
+fn  junit_failure(f: &Failure, rerun: Option<&str>) -> String
+{
+    let tag = match (rerun, f.assertion)
+    {
+        (None, true) => "failure".to_string(),
+        (None, false) => "error".to_string(),
+        (Some(prefix), true) => format!("{prefix}Failure"),
+        (Some(prefix), false) => format!("{prefix}Error"),
+    };
+    let kind = exception_type(f).map(|t| format!(" type=\"{}\"", xml_escape(t))).unwrap_or_default();
+    let trace = xml_escape(f.trace.as_deref().unwrap_or(&f.message));
+    match rerun
+    {
+        None => format!("      <{tag} message=\"{}\"{kind}>{trace}</{tag}>\n", xml_escape(&f.message)),
+        Some(_) => format!("      <{tag} message=\"{}\"{kind}><stackTrace>{trace}</stackTrace></{tag}>\n", xml_escape(&f.message)),
+    }
+}
+
+/// Failures and errors as JUnit counts them: quarantined ones included.
+#[inline(always)]
+fn  junit_counts<'a>(results: impl Iterator<Item = &'a TestResult> + Clone) -> (usize, usize)
+{
+    (results.clone().filter(|r| r.is_failure()).count(), results.filter(|r| r.is_error()).count())
+}
+
+/// One `<testsuite>` per class, one `<testcase>` per test method. Classes run
+/// through `main` and class-level failures are a testcase named after the class.
+/// Quarantined tests carry a `quarantined` property.
+pub fn  junit(results: &[TestResult]) -> String
+{
+    let mut suites: BTreeMap<&str, Vec<&TestResult>> = BTreeMap::new();
+    for r in results
+    {
+        suites.entry(&r.class).or_default().push(r);
+    }
+    let total: Duration = results.iter().map(|r| r.duration).sum();
+    let all = Summary::of(results);
+    let (failed, errored) = junit_counts(results.iter());
+
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!(
+        "<testsuites name=\"lvjb\" tests=\"{}\" failures=\"{failed}\" errors=\"{errored}\" skipped=\"{}\" time=\"{}\">\n",
+        results.len(), all.skipped, seconds(total)));
+    for (class, rs) in &suites
+    {
+        let time: Duration = rs.iter().map(|r| r.duration).sum();
+        let sum = Summary::of(rs.iter().copied());
+        let (failed, errored) = junit_counts(rs.iter().copied());
+        out.push_str(&format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{failed}\" errors=\"{errored}\" skipped=\"{}\" time=\"{}\">\n",
+            xml_escape(class), rs.len(), sum.skipped, seconds(time)));
+        for r in rs
+        {
+            let name = xml_escape(r.method.as_deref().unwrap_or(&r.class));
+            let head = format!("    <testcase classname=\"{}\" name=\"{name}\" time=\"{}\"", xml_escape(class), seconds(r.duration));
+            let mut body = match r.quarantined
+            {
+                true => "      <properties>\n        <property name=\"quarantined\" value=\"true\"/>\n      </properties>\n".to_string(),
+                false => String::new(),
+            };
+            match &r.outcome
+            {
+                Outcome::Passed => (),
+                Outcome::Skipped => body.push_str("      <skipped/>\n"),
+                Outcome::Failed(f) => body.push_str(&junit_failure(f, None)),
+            }
+            let rerun = if r.outcome == Outcome::Passed { "flaky" } else { "rerun" };
+            for f in &r.retried
+            {
+                body.push_str(&junit_failure(f, Some(rerun)));
+            }
+            if !r.output.is_empty()
+            {
+                body.push_str(&format!("      <system-out>{}</system-out>\n", xml_escape(&r.output)));
+            }
+            match body.is_empty()
+            {
+                true => out.push_str(&format!("{head}/>\n")),
+                false => out.push_str(&format!("{head}>\n{body}    </testcase>\n")),
+            }
+        }
+        out.push_str("  </testsuite>\n");
+    }
+    out.push_str("</testsuites>\n");
+    out
+}
+
+/// TAP version 13; failures carry a YAML block with the message, trace and
+/// captured output. Quarantined failures are marked `TODO`, which TAP doesn't
+/// count as failing, and flaky passes note their attempts in a YAML block.
+pub fn  tap(results: &[TestResult]) -> String
+{
+    let mut out = format!("TAP version 13\n1..{}\n", results.len());
+    for (i, r) in results.iter().enumerate()
+    {
+        let n = i + 1;
+        match &r.outcome
+        {
+            Outcome::Passed if r.is_flaky() =>
+            {
+                let message = serde_json::to_string(&r.retried[0].message).unwrap_or_default();
+                out.push_str(&format!("ok {n} - {}\n  ---\n  flaky: true\n  attempts: {}\n  first_failure: {message}\n  ...\n", r.name(), r.retried.len() + 1));
+            }
+            Outcome::Passed => out.push_str(&format!("ok {n} - {}\n", r.name())),
+            Outcome::Skipped => out.push_str(&format!("ok {n} - {} # SKIP\n", r.name())),
+            Outcome::Failed(f) =>
+            {
+                let todo = if r.quarantined { " # TODO quarantined" } else { "" };
+                out.push_str(&format!("not ok {n} - {}{todo}\n  ---\n", r.name()));
+                // JSON strings are valid YAML scalars and take care of quoting.
+                let message = serde_json::to_string(&f.message).unwrap_or_default();
+                out.push_str(&format!("  message: {message}\n  duration_ms: {}\n", r.duration.as_millis()));
+                if !r.retried.is_empty()
+                {
+                    out.push_str(&format!("  attempts: {}\n", r.retried.len() + 1));
+                }
+                for (key, text) in [("stack", f.trace.as_deref().unwrap_or_default()), ("output", r.output.as_str())]
+                {
+                    if text.is_empty()
+                    {
+                        continue;
+                    }
+                    out.push_str(&format!("  {key}: |\n"));
+                    for line in text.lines()
+                    {
+                        out.push_str(&format!("    {line}\n"));
+                    }
+                }
+                out.push_str("  ...\n");
+            }
+        }
+    }
+    out
+}
+
+/// Writes every requested report. Results are ordered by class so reports
+/// are stable across runs.
+pub fn  write_reports(reports: &[Report], results: &[TestResult]) -> Result<(), Box<dyn std::error::Error>>
+{
+    let mut sorted = results.to_vec();
+    sorted.sort_by(|a, b| a.class.cmp(&b.class));
+    for report in reports
+    {
+        match report
+        {
+            Report::Junit(path) =>
+            {
+                fs::write(path, junit(&sorted))?;
+                eprintln!("{GREEN}[REPORT]{RESET} JUnit XML written to {}", path.display());
+            }
+            Report::Tap(Some(path)) =>
+            {
+                fs::write(path, tap(&sorted))?;
+                eprintln!("{GREEN}[REPORT]{RESET} TAP written to {}", path.display());
+            }
+            Report::Tap(None) => print!("{}", tap(&sorted)),
+        }
+    }
+    Ok(())
+}
+
+/// Per-class counts and times, then the totals, on stderr.
+pub fn  print_table(results: &[TestResult], summary: &Summary)
+{
+    let mut classes: BTreeMap<&str, (Summary, Duration)> = BTreeMap::new();
+    for r in results
+    {
+        let entry = classes.entry(&r.class).or_default();
+        entry.0.add(r);
+        entry.1 += r.duration;
+    }
+    let width = classes.keys().map(|c| c.len()).max().unwrap_or(0).max("total".len());
+    let row = |name: &str, s: &Summary, time: Duration|
+        eprintln!("  {name:<width$}  {:>6}  {:>6}  {:>7}  {:>7}  {:>5}  {:>11}  {:>8}s", s.passed, s.failed, s.errored, s.skipped, s.flaky, s.quarantined, seconds(time));
+
+    let color = if summary.success() { GREEN } else { RED };
+    eprintln!("{color}[TEST SUMMARY]{RESET}");
+    eprintln!("  {:<width$}  {:>6}  {:>6}  {:>7}  {:>7}  {:>5}  {:>11}  {:>9}", "class", "passed", "failed", "errored", "skipped", "flaky", "quarantined", "time");
+    for (class, (s, time)) in &classes
+    {
+        row(class, s, *time);
+    }
+    row("total", summary, results.iter().map(|r| r.duration).sum());
+}
@@ -0,0 +1,157 @@
+use crate::config::*;
+use crate::parallel::job_count;
+use crate::spawn::{ORANGE, RED, RESET};
+use crate::testrunner::*;
+use crate::watchdog::*;
+use jni::JavaVM;
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+use std::io::{stderr, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// What a pool run leaves behind.
+pub struct PoolRun
+{
+    pub results:    Vec<TestResult>,
+    /// Wall time per test class in milliseconds, for the next run's ordering.
+    pub durations:  HashMap<String, u64>,
+    /// Classes never started because of `--fail-fast`.
+    pub not_run:    usize,
+}
+
+struct Shared
+{
+    queue:      Mutex<VecDeque<String>>,
+    results:    Mutex<Vec<TestResult>>,
+    durations:  Mutex<HashMap<String, u64>>,
+    halt:       AtomicBool,
+    /// Set once the pool has handed its results on, so a worker given up on
+    /// that returns afterwards doesn't print into the summary.
+    closed:     AtomicBool,
+}
+
+/// Slowest classes first, by the durations of earlier runs in lvjb.lock.
+/// Classes without a recorded duration go before all of them.
+pub fn  schedule(mut classes: Vec<String>, config: &Config) -> VecDeque<String>
+{
+    classes.sort_by_key(|c| Reverse(config.cache.test_durations.get(c).copied().unwrap_or(u64::MAX)));
+    classes.into()
+}
+
+#[inline(always)]
+fn  millis(d: Duration) -> u64
+{
+    d.as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+/// Pulls classes off the queue until it is empty. Returns early, for good,
+/// when one of its tests hung and was given up on; if that test ever
+/// returns, the other results of its class are still kept.
+fn  worker(shared: &Shared, config: &Config, jvm: &'static JavaVM, options: &TestOptions, hung: &HungSlot)
+{
+    let fail_fast = options.fail_fast;
+    loop
+    {
+        if fail_fast && shared.halt.load(Ordering::Relaxed)
+        {
+            return;
+        }
+        let Some(class) = shared.queue.lock().ok().and_then(|mut q| q.pop_front()) else { return };
+        let started = Instant::now();
+        let mut results = run_test_class(&class, config, jvm, fail_fast.then_some(&shared.halt), hung, &options.filter, options.retries);
+        // The hung test was already reported through `hung`.
+        let given_up = hung.lock().ok().and_then(|x| x.as_ref().map(|r| (r.class.clone(), r.method.clone())));
+        if let Some((class, method)) = &given_up
+        {
+            results.retain(|r| r.class != *class || r.method != *method);
+        }
+        else if let Ok(mut d) = shared.durations.lock()
+        {
+            d.insert(class, millis(started.elapsed()));
+        }
+        if let Ok(mut r) = shared.results.lock()
+            && !shared.closed.load(Ordering::Relaxed)
+        {
+            results.iter().for_each(|r| print_outcome(r, options.nocapture));
+            let _ = stderr().flush();
+            r.extend(results);
+        }
+        if given_up.is_some()
+        {
+            return;
+        }
+    }
+}
+
+/// Runs `classes` on `-j` workers (one per core by default) that share a
+/// queue, so a slow class only holds up the worker running it. A worker
+/// stuck in a test that couldn't be stopped is left behind and replaced.
+pub fn  run_pool(classes: Vec<String>, config: &Config, jvm: &'static JavaVM, options: &TestOptions) -> Result<PoolRun, Box<dyn std::error::Error>>
+{
+    let fail_fast = options.fail_fast;
+    let workers = job_count(config).min(classes.len()).max(1);
+    eprintln!("{ORANGE}[TESTRUNNER]{RESET} {} test class(es) on {workers} worker(s)", classes.len());
+    let shared = Arc::new(Shared
+    {
+        queue:      Mutex::new(schedule(classes, config)),
+        results:    Mutex::new(Vec::new()),
+        durations:  Mutex::new(HashMap::new()),
+        halt:       AtomicBool::new(false),
+        closed:     AtomicBool::new(false),
+    });
+    let (config, options) = (Arc::new(config.clone()), Arc::new(options.clone()));
+    let spawn = ||
+    {
+        let hung = HungSlot::default();
+        let (shared, config, options, slot) = (Arc::clone(&shared), Arc::clone(&config), Arc::clone(&options), Arc::clone(&hung));
+        (thread::spawn(move || worker(&shared, &config, jvm, &options, &slot)), hung)
+    };
+    let mut pool: Vec<(JoinHandle<()>, HungSlot)> = (0..workers).map(|_| spawn()).collect();
+    let lock_err = |e: String| format!("{RED}[TESTRUNNER]{RESET} Failed to lock test results: {e}");
+    while !pool.is_empty()
+    {
+        let mut i = 0;
+        while i < pool.len()
+        {
+            if let Some(mut r) = pool[i].1.lock().ok().and_then(|x| x.clone())
+            {
+                r.quarantined = quarantined(&config, &r.class, r.method.as_deref());
+                print_outcome(&r, options.nocapture);
+                shared.durations.lock().map_err(|e| lock_err(e.to_string()))?.insert(r.class.clone(), millis(r.duration));
+                if fail_fast && !r.quarantined
+                {
+                    shared.halt.store(true, Ordering::Relaxed);
+                }
+                shared.results.lock().map_err(|e| lock_err(e.to_string()))?.push(r);
+                pool.swap_remove(i);
+                pool.push(spawn());
+            }
+            else if pool[i].0.is_finished()
+            {
+                let (handle, _) = pool.swap_remove(i);
+                if handle.join().is_err()
+                {
+                    return Err(format!("{RED}[TESTRUNNER]{RESET} A test worker panicked").into());
+                }
+            }
+            else
+            {
+                i += 1;
+            }
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let not_run = shared.queue.lock().map_err(|e| lock_err(e.to_string()))?.len();
+    let results =
+    {
+        let mut results = shared.results.lock().map_err(|e| lock_err(e.to_string()))?;
+        shared.closed.store(true, Ordering::Relaxed);
+        std::mem::take(&mut *results)
+    };
+    let durations = std::mem::take(&mut *shared.durations.lock().map_err(|e| lock_err(e.to_string()))?);
+    Ok(PoolRun { results, durations, not_run })
+}
@@ -1,5 +1,8 @@
 use crate::config::*;
 use crate::fs::*;
+use crate::compiler::*;
+use crate::diagnostics::*;
+use crate::parallel::*;
 use std::process::*;
 use std::path::PathBuf;
 
@@ -8,7 +11,7 @@ pub const GREEN: &str = "\x1b[32m";
 pub const RED: &str = "\x1b[31m";
 pub const RESET: &str = "\x1b[0m";
 
-pub fn  spawn_compilation_command(files: &Vec<PathBuf>, config: &Config) -> Result<(), Box<dyn std::error::Error>>
+pub fn  spawn_compilation_command(files: &[PathBuf], config: &Config) -> Result<(), Box<dyn std::error::Error>>
 {
     run_hooks(&config.pre_build_cmds)?;
     if files.is_empty()
@@ -16,43 +19,123 @@ pub fn  spawn_compilation_command(files: &Vec<PathBuf>, config: &Config) -> Resu
         eprintln!("{GREEN}[COMPILER]{RESET} Nothing to compile");
         return Ok(());
     }
-    let mut command = Command::new(&config.compiler);
+    let json = config.message_format == MESSAGE_FORMAT_JSON;
     let classpath = expand_classpath(&config.classpath);
-    if !config.classpath.is_empty()
+    let mut listing = format!("{ORANGE}[COMPILER]{RESET} classpath: {}, output to: {}", &classpath, &config.paths.bin);
+    let total = files.len();
+    for (i, file) in files.iter().enumerate()
     {
-        command.arg("-cp").arg(&classpath);
+        let symbol = if i < total - 1 { "├ " } else { "└ " };
+        listing.push_str(&format!("\n  {} {}", symbol, file.to_string_lossy()));
     }
-    command.arg("-d").arg(&config.paths.bin);
-    for file in files
+    // Keep stdout clean for the JSON stream.
+    if json
     {
-        command.arg(file);
+        eprintln!("{listing}");
     }
-    if let Some(x) = &config.args.compilation
+    else
     {
-        command.args(x);
+        println!("{listing}");
     }
-    println!("{ORANGE}[COMPILER]{RESET} classpath: {}, output to: {}", &classpath, &config.paths.bin);
-    let total = files.len();
-    for (i, file) in files.iter().enumerate()
+
+    let (ok, diagnostics, status) = if config.compiler == IN_PROCESS_COMPILER
     {
-        let symbol = if i < total - 1 { "├ " } else { "└ " };
-        println!("  {} {}", symbol, file.to_string_lossy());
+        let (ok, diagnostics) = compile_in_process(files, config)
+            .map_err(|e| format!("{RED}[COMPILER ERROR]{RESET} {e}"))?;
+        if !json
+        {
+            for d in &diagnostics
+            {
+                eprintln!("{d}");
+            }
+        }
+        (ok, diagnostics, None)
+    }
+    else if let Some(units) = compilation_units(files, config)
+    {
+        compile_parallel(units, config, &classpath)?
+    }
+    else
+    {
+        let output = run_javac(files, config, &classpath)?;
+        if !json
+        {
+            eprint!("{}", output.stderr);
+        }
+        (output.status.success(), parse_javac(&output.stderr), Some(output.status))
+    };
+
+    if json
+    {
+        print_json(&diagnostics);
+    }
+    else
+    {
+        print_summary(&diagnostics);
+    }
+    if !ok
+    {
+        let errors = diagnostics.iter().filter(|x| x.severity == Severity::Error).count();
+        return Err(match status
+        {
+            Some(status) => format!("{RED}[COMPILER ERROR]{RESET} Compilation failed with {errors} error(s), status: {status}"),
+            None => format!("{RED}[COMPILER ERROR]{RESET} Compilation failed with {errors} error(s)"),
+        }.into());
     }
-    match command.status()
+    if json
     {
-        Ok(status) if status.success() => println!("{GREEN}[COMPILER OK]{RESET} Compilation succeeded."),
-        Ok(status) => { return Err(format!("{RED}[COMPILER ERROR]{RESET} Compilation failed with status: {status}").into());},
-        Err(err) => { return Err(format!("{RED}[COMPILER ERROR]{RESET} Failed to execute command: {err}").into()); },
+        eprintln!("{GREEN}[COMPILER OK]{RESET} Compilation succeeded.");
+    }
+    else
+    {
+        println!("{GREEN}[COMPILER OK]{RESET} Compilation succeeded.");
     }
     run_hooks(&config.post_build_cmds)?;
     Ok(())
 }
 
+/// Whether compilation succeeded, its diagnostics and javac's exit status
+/// when an external process ran last.
+pub type CompileResult = (bool, Vec<Diagnostic>, Option<ExitStatus>);
+
+pub struct JavacOutput
+{
+    pub status: ExitStatus,
+    pub stderr: String,
+}
+
+/// Runs the external compiler over `files`, capturing its output.
+pub fn  run_javac(files: &[PathBuf], config: &Config, classpath: &str) -> Result<JavacOutput, Box<dyn std::error::Error>>
+{
+    let mut command = Command::new(&config.compiler);
+    if !classpath.is_empty()
+    {
+        command.arg("-cp").arg(classpath);
+    }
+    command.arg("-d").arg(&config.paths.bin);
+    for file in files
+    {
+        command.arg(file);
+    }
+    if let Some(x) = &config.args.compilation
+    {
+        command.args(x);
+    }
+    let output = command.output()
+        .map_err(|err| format!("{RED}[COMPILER ERROR]{RESET} Failed to execute command: {err}"))?;
+    eprint!("{}", String::from_utf8_lossy(&output.stdout));
+    Ok(JavacOutput
+    {
+        status: output.status,
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    })
+}
+
 #[inline(always)]
 pub fn  run_hooks(hooks: &Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
     for s in hooks {
         eprintln!("{ORANGE}[PRECOMP HOOK]{RESET} Running {}", s);
-        let status = Command::new("sh").arg("-c").arg(&s).status();
+        let status = Command::new("sh").arg("-c").arg(s).status();
         match status
         {
             Ok(code) if code.success() => continue,
@@ -0,0 +1,1080 @@
+use crate::config::*;
+use crate::jvm::*;
+use crate::fs::SUPPORT_DIR;
+use crate::report::*;
+use crate::watchdog::*;
+use crate::capture::*;
+use crate::coverage::*;
+use crate::filter::*;
+use crate::spawn::{ORANGE, RED, RESET};
+use jni::objects::*;
+use jni::signature::{Primitive, ReturnType};
+use jni::sys::jmethodID;
+use jni::{JNIEnv, JavaVM};
+use serde::{Deserialize, Serialize};
+use std::{env, fmt};
+use std::fs;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+
+/// Minimal annotations generated in `SUPPORT_DIR` so tests don't need JUnit.
+/// Discovery matches on simple names, so JUnit 4/5 annotations work too.
+const ANNOTATIONS: [&str; 6] = ["Test", "Before", "After", "BeforeAll", "AfterAll", "Disabled"];
+
+/// `@Tag("slow")` on a test method or class, repeatable like JUnit 5's.
+const TAG: &str = "package lvjb;
+
+import java.lang.annotation.*;
+
+@Retention(RetentionPolicy.RUNTIME)
+@Target({ElementType.TYPE, ElementType.METHOD})
+@Repeatable(Tags.class)
+public @interface Tag {
+    String value();
+}
+";
+
+/// Container of repeated `@Tag`s.
+const TAGS: &str = "package lvjb;
+
+import java.lang.annotation.*;
+
+@Retention(RetentionPolicy.RUNTIME)
+@Target({ElementType.TYPE, ElementType.METHOD})
+public @interface Tags {
+    Tag[] value();
+}
+";
+
+const TEST: [&str; 1] = ["Test"];
+const BEFORE: [&str; 2] = ["Before", "BeforeEach"];
+const AFTER: [&str; 2] = ["After", "AfterEach"];
+const BEFORE_ALL: [&str; 2] = ["BeforeClass", "BeforeAll"];
+const AFTER_ALL: [&str; 2] = ["AfterClass", "AfterAll"];
+const SKIP: [&str; 2] = ["Ignore", "Disabled"];
+
+/// One VM, one class loader per test class: methods of a class share statics.
+pub const ISOLATION_SHARED: &str = "shared";
+/// One VM, a fresh class loader (and so fresh statics) per test method.
+pub const ISOLATION_CLASSLOADER: &str = "classloader";
+/// A separate lvjb process, with its own VM, per test method.
+pub const ISOLATION_PROCESS: &str = "process";
+
+/// Hidden command the process isolation mode runs a single test through.
+pub const WORKER_CMD: &str = "test-worker";
+
+/// Security manager generated next to the annotations. It turns `System.exit`
+/// into an exception so a test can't take the runner down with it.
+pub const EXIT_GUARD: &str = "package lvjb;
+
+@SuppressWarnings(\"removal\")
+public final class ExitGuard extends SecurityManager {
+    public static final class Exit extends SecurityException {
+        public Exit(int status) { super(\"System.exit(\" + status + \") called\"); }
+    }
+
+    public static boolean install() {
+        try {
+            System.setSecurityManager(new ExitGuard());
+            return true;
+        } catch (UnsupportedOperationException e) {
+            return false;
+        }
+    }
+
+    public static void uninstall() {
+        SecurityManager current = System.getSecurityManager();
+        // Compared by name: the guard may come from another test class loader.
+        if (current != null && current.getClass().getName().equals(ExitGuard.class.getName())) {
+            System.setSecurityManager(null);
+        }
+    }
+
+    @Override public void checkExit(int status) { throw new Exit(status); }
+    @Override public void checkPermission(java.security.Permission perm) {}
+    @Override public void checkPermission(java.security.Permission perm, Object context) {}
+}
+";
+
+/// Exit code of `lvjb test` when any test failed or errored, so CI can tell
+/// broken tests from a broken build (exit code 1).
+pub const EXIT_TESTS_FAILED: i32 = 2;
+
+/// What a failing test threw: its `toString()` and, when it came from a
+/// Java exception, the printed stack trace. Only `AssertionError`s count as
+/// test failures; anything else is an error.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Failure
+{
+    pub message:    String,
+    pub trace:      Option<String>,
+    pub assertion:  bool,
+}
+
+impl Failure
+{
+    #[inline(always)]
+    fn  context(&self, what: &str) -> Self
+    {
+        Failure { message: format!("{what}: {}", self.message), trace: self.trace.clone(), assertion: self.assertion }
+    }
+}
+
+impl From<String> for Failure
+{
+    #[inline(always)]
+    fn  from(message: String) -> Self
+    {
+        Failure { message, trace: None, assertion: false }
+    }
+}
+
+impl fmt::Display for Failure
+{
+    fn  fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Outcome
+{
+    Passed,
+    Failed(Failure),
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TestResult
+{
+    pub class:      String,
+    pub method:     Option<String>,
+    pub outcome:    Outcome,
+    pub duration:   Duration,
+    /// What the test printed to `System.out`/`System.err`.
+    #[serde(default)]
+    pub output:     String,
+    /// Failures of earlier attempts, with `--retries`.
+    #[serde(default)]
+    pub retried:    Vec<Failure>,
+    /// On the `quarantine` list: a failure doesn't fail the build.
+    #[serde(default)]
+    pub quarantined: bool,
+}
+
+impl TestResult
+{
+    #[inline(always)]
+    pub fn  new(class: &str, method: Option<String>, outcome: Outcome, duration: Duration) -> Self
+    {
+        TestResult { class: class.to_string(), method, outcome, duration, output: String::new(), retried: Vec::new(), quarantined: false }
+    }
+
+    /// Passed, but only after failing.
+    #[inline(always)]
+    pub fn  is_flaky(&self) -> bool
+    {
+        self.outcome == Outcome::Passed && !self.retried.is_empty()
+    }
+
+    /// Failed, and not quarantined.
+    #[inline(always)]
+    pub fn  fails_build(&self) -> bool
+    {
+        matches!(self.outcome, Outcome::Failed(_)) && !self.quarantined
+    }
+
+    #[inline(always)]
+    pub fn  is_error(&self) -> bool
+    {
+        matches!(&self.outcome, Outcome::Failed(f) if !f.assertion)
+    }
+
+    #[inline(always)]
+    pub fn  is_failure(&self) -> bool
+    {
+        matches!(&self.outcome, Outcome::Failed(f) if f.assertion)
+    }
+
+    #[inline(always)]
+    pub fn  name(&self) -> String
+    {
+        match &self.method
+        {
+            Some(m) => format!("{}.{m}", self.class),
+            None => self.class.clone(),
+        }
+    }
+}
+
+/// Totals of a test run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary
+{
+    pub passed:     usize,
+    pub failed:     usize,
+    pub errored:    usize,
+    pub skipped:    usize,
+    pub not_run:    usize,
+    /// Passed on a retry; also counted as passed.
+    pub flaky:      usize,
+    /// Failed, but quarantined.
+    pub quarantined: usize,
+}
+
+impl Summary
+{
+    #[inline(always)]
+    pub fn  add(&mut self, result: &TestResult)
+    {
+        match &result.outcome
+        {
+            Outcome::Passed =>
+            {
+                self.passed += 1;
+                self.flaky += usize::from(result.is_flaky());
+            }
+            Outcome::Skipped => self.skipped += 1,
+            Outcome::Failed(_) if result.quarantined => self.quarantined += 1,
+            Outcome::Failed(f) if f.assertion => self.failed += 1,
+            Outcome::Failed(_) => self.errored += 1,
+        }
+    }
+
+    pub fn  of<'a>(results: impl IntoIterator<Item = &'a TestResult>) -> Self
+    {
+        let mut out = Summary::default();
+        for r in results
+        {
+            out.add(r);
+        }
+        out
+    }
+
+    #[inline(always)]
+    pub fn  success(&self) -> bool
+    {
+        self.failed == 0 && self.errored == 0
+    }
+}
+
+/// Flags of `lvjb test`.
+#[derive(Debug, Clone, Default)]
+pub struct TestOptions
+{
+    pub reports:    Vec<Report>,
+    /// Stop starting new tests after the first failure.
+    pub fail_fast:  bool,
+    /// Show the output of every test, not only of failing ones.
+    pub nocapture:  bool,
+    /// Which tests to run.
+    pub filter:     TestFilter,
+    /// Only run test classes touching a source changed since they last passed.
+    pub changed:    bool,
+    /// Record line coverage and write an LCOV report.
+    pub coverage:   bool,
+    /// How many times to rerun a failed test before giving up on it.
+    pub retries:    u32,
+}
+
+#[inline(always)]
+fn  retries(count: &str) -> Result<u32, String>
+{
+    count.parse().map_err(|_| format!("--retries expects a number, got '{count}'"))
+}
+
+impl TestOptions
+{
+    /// Parses the arguments following `lvjb test`.
+    pub fn  parse(args: &[String]) -> Result<Self, String>
+    {
+        let mut out = TestOptions::default();
+        let mut it = args.iter();
+        while let Some(arg) = it.next()
+        {
+            match arg.as_str()
+            {
+                "--report" =>
+                {
+                    let spec = it.next().ok_or("--report needs a format: junit=<path> or tap[=<path>]")?;
+                    out.reports.push(Report::parse(spec)?);
+                }
+                x if x.starts_with("--report=") => out.reports.push(Report::parse(&x["--report=".len()..])?),
+                "--skip" => out.filter.skip.push(it.next().ok_or("--skip needs a pattern")?.clone()),
+                x if x.starts_with("--skip=") => out.filter.skip.push(x["--skip=".len()..].to_string()),
+                "--include-tag" => out.filter.include_tags.push(it.next().ok_or("--include-tag needs a tag")?.clone()),
+                x if x.starts_with("--include-tag=") => out.filter.include_tags.push(x["--include-tag=".len()..].to_string()),
+                "--exact" => out.filter.exact = true,
+                "--changed" => out.changed = true,
+                "--coverage" => out.coverage = true,
+                "--retries" => out.retries = retries(it.next().ok_or("--retries needs a count")?)?,
+                x if x.starts_with("--retries=") => out.retries = retries(&x["--retries=".len()..])?,
+                "--fail-fast" => out.fail_fast = true,
+                "--no-fail-fast" => out.fail_fast = false,
+                "--nocapture" => out.nocapture = true,
+                // `-j`'s value is not a pattern.
+                "-j" => { it.next(); }
+                x if !x.starts_with('-') => out.filter.patterns.push(x.to_string()),
+                _ => (),
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// One `[TEST ...]` line for a result that wasn't a pass, followed by the
+/// test's output if it failed (or always, with `nocapture`). Written at once
+/// so parallel workers don't interleave.
+pub fn  print_outcome(r: &TestResult, nocapture: bool)
+{
+    let attempts = match r.retried.len()
+    {
+        0 => String::new(),
+        n => format!(" ({} attempts)", n + 1),
+    };
+    let mut text = match &r.outcome
+    {
+        Outcome::Passed if r.is_flaky() => format!("{ORANGE}[TEST FLAKY]{RESET} {} passed on attempt {}, first failed with: {}\n", r.name(), r.retried.len() + 1, r.retried[0]),
+        Outcome::Passed => String::new(),
+        Outcome::Skipped => format!("{ORANGE}[TEST SKIPPED]{RESET} {}\n", r.name()),
+        Outcome::Failed(e) if r.quarantined => format!("{ORANGE}[TEST QUARANTINED]{RESET} {}: {e}{attempts}\n", r.name()),
+        Outcome::Failed(e) if e.assertion => format!("{RED}[TEST FAILED]{RESET} {}: {e}{attempts}\n", r.name()),
+        Outcome::Failed(e) => format!("{RED}[TEST ERROR]{RESET} {}: {e}{attempts}\n", r.name()),
+    };
+    if !r.output.is_empty() && (nocapture || matches!(r.outcome, Outcome::Failed(_)))
+    {
+        text.push_str(&format!("{ORANGE}[TEST OUTPUT]{RESET} {}\n{}", r.name(), r.output));
+        if !r.output.ends_with('\n')
+        {
+            text.push('\n');
+        }
+    }
+    eprint!("{text}");
+}
+
+/// Writes the lvjb annotations, tags, exit guard, output capture and coverage
+/// recorder to `SUPPORT_DIR` and returns the sources to compile with the
+/// tests. Copies older versions of lvjb wrote into the test tree are removed;
+/// one that was edited there is used instead of the generated one.
+pub fn  ensure_test_support(config: &Config) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>>
+{
+    let dir = PathBuf::from(SUPPORT_DIR).join("lvjb");
+    let legacy = PathBuf::from(&config.paths.test).join("lvjb");
+    let annotations = ANNOTATIONS.map(|name| (name, format!(
+        "package lvjb;\n\n\
+        import java.lang.annotation.*;\n\n\
+        @Retention(RetentionPolicy.RUNTIME)\n\
+        @Target(ElementType.METHOD)\n\
+        public @interface {name} {{}}\n")));
+    let sources = [("ExitGuard", EXIT_GUARD), ("Capture", CAPTURE), ("Coverage", COVERAGE), ("Tag", TAG), ("Tags", TAGS)]
+        .map(|(name, source)| (name, source.to_string()));
+    let (mut out, mut moved) = (Vec::new(), 0);
+    for (name, source) in sources.into_iter().chain(annotations)
+    {
+        let file = format!("{name}.{}", config.src_ext);
+        let path = dir.join(&file);
+        if let Ok(old) = fs::read_to_string(legacy.join(&file))
+        {
+            if old != source
+            {
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+            fs::remove_file(legacy.join(&file))?;
+            moved += 1;
+        }
+        if fs::read_to_string(&path).ok().as_ref() != Some(&source)
+        {
+            fs::create_dir_all(&dir)?;
+            fs::write(&path, source)?;
+        }
+        out.push(path);
+    }
+    if moved > 0
+    {
+        eprintln!("{ORANGE}[TESTRUNNER]{RESET} Removed {moved} generated source(s) from {}, lvjb now keeps them in {SUPPORT_DIR}", legacy.display());
+    }
+    if fs::read_dir(&legacy).is_ok_and(|mut d| d.next().is_none())
+    {
+        let _ = fs::remove_dir(&legacy);
+    }
+    Ok(out)
+}
+
+/// Binary class name of a test source, from its path under `paths.test`.
+/// The lvjb support sources are not tests.
+#[inline(always)]
+pub fn  test_class_name(file: &Path, config: &Config) -> Option<String>
+{
+    let relative = file.strip_prefix(&config.paths.test).ok()?;
+    if relative.starts_with("lvjb")
+    {
+        return None;
+    }
+    Some(relative.with_extension("")
+        .to_string_lossy()
+        .replace("/", ".")
+        .replace("\\", "."))
+}
+
+struct TestMethod<'a>
+{
+    name:   String,
+    method: JObject<'a>,
+    skip:   bool,
+    /// Its own `@Tag`s and those of its class.
+    tags:   Vec<String>,
+    /// Why it can't be called, see `unfit`.
+    unfit:  Option<Failure>,
+}
+
+struct Methods<'a>
+{
+    tests:      Vec<TestMethod<'a>>,
+    before:     Vec<JObject<'a>>,
+    after:      Vec<JObject<'a>>,
+    before_all: Vec<JObject<'a>>,
+    after_all:  Vec<JObject<'a>>,
+    has_main:   bool,
+    /// `@Tag`s of the class itself.
+    tags:       Vec<String>,
+    /// Hooks that can't be called, see `unfit`.
+    unfit:      Vec<Failure>,
+}
+
+fn  string_of<'a>(env: &mut JNIEnv<'a>, obj: &JObject, method: &str) -> Result<String, jni::errors::Error>
+{
+    let s = env.call_method(obj, method, "()Ljava/lang/String;", &[])?.l()?;
+    Ok(env.get_string(&JString::from(s))?.into())
+}
+
+/// Simple names of the annotations on a class or method, and the values of
+/// its `@Tag`s, repeated ones included.
+fn  annotations(env: &mut JNIEnv, element: &JObject) -> Result<(Vec<String>, Vec<String>), jni::errors::Error>
+{
+    let array = JObjectArray::from(env.call_method(element, "getAnnotations", "()[Ljava/lang/annotation/Annotation;", &[])?.l()?);
+    let (mut names, mut tags) = (Vec::new(), Vec::new());
+    for i in 0..env.get_array_length(&array)?
+    {
+        let name = env.with_local_frame(16, |env| -> Result<String, jni::errors::Error>
+        {
+            let annotation = env.get_object_array_element(&array, i)?;
+            let kind = env.call_method(&annotation, "annotationType", "()Ljava/lang/Class;", &[])?.l()?;
+            let name = string_of(env, &kind, "getSimpleName")?;
+            match name.as_str()
+            {
+                "Tag" => tags.push(string_of(env, &annotation, "value")?),
+                "Tags" =>
+                {
+                    let (value, null) = (env.new_string("value")?, JObject::null());
+                    let getter = env.call_method(&kind, "getMethod", "(Ljava/lang/String;[Ljava/lang/Class;)Ljava/lang/reflect/Method;", &[JValue::Object(&value), JValue::Object(&null)])?.l()?;
+                    let repeated = JObjectArray::from(env.call_method(&getter, "invoke", "(Ljava/lang/Object;[Ljava/lang/Object;)Ljava/lang/Object;", &[JValue::Object(&annotation), JValue::Object(&null)])?.l()?);
+                    for j in 0..env.get_array_length(&repeated)?
+                    {
+                        let tag = env.get_object_array_element(&repeated, j)?;
+                        tags.push(string_of(env, &tag, "value")?);
+                        env.delete_local_ref(tag)?;
+                    }
+                }
+                _ => (),
+            }
+            Ok(name)
+        })?;
+        names.push(name);
+    }
+    Ok((names, tags))
+}
+
+/// Why `method` can't be run as a test or hook, if it can't: `invoke` calls
+/// it with no arguments, expects nothing back, and calls it on an instance
+/// unless `statik`. Anything else would be undefined behaviour in the VM.
+fn  unfit(env: &mut JNIEnv, method: &JObject, name: &str, statik: bool) -> Result<Option<Failure>, jni::errors::Error>
+{
+    let params = env.call_method(method, "getParameterCount", "()I", &[])?.i()?;
+    let modifiers = env.call_method(method, "getModifiers", "()I", &[])?.i()?;
+    let is_static = env.call_static_method("java/lang/reflect/Modifier", "isStatic", "(I)Z", &[JValue::Int(modifiers)])?.z()?;
+    let returns = env.call_method(method, "getReturnType", "()Ljava/lang/Class;", &[])?.l()?;
+    let void = string_of(env, &returns, "getName")? == "void";
+    let kind = if statik { "static" } else { "instance" };
+    Ok((params != 0 || is_static != statik || !void).then(|| format!("{name} must be a no-arg {kind} method returning void").into()))
+}
+
+fn  discover<'a>(env: &mut JNIEnv<'a>, class: &JClass) -> Result<Methods<'a>, jni::errors::Error>
+{
+    let (_, tags) = annotations(env, class)?;
+    let mut out = Methods
+    {
+        tests: Vec::new(), before: Vec::new(), after: Vec::new(),
+        before_all: Vec::new(), after_all: Vec::new(), has_main: false, tags, unfit: Vec::new(),
+    };
+    let methods = JObjectArray::from(env.call_method(class, "getDeclaredMethods", "()[Ljava/lang/reflect/Method;", &[])?.l()?);
+    for i in 0..env.get_array_length(&methods)?
+    {
+        let method = env.get_object_array_element(&methods, i)?;
+        let name = string_of(env, &method, "getName")?;
+        if name == "main"
+        {
+            out.has_main = true;
+        }
+        let (names, mut tags) = annotations(env, &method)?;
+        if names.is_empty()
+        {
+            continue;
+        }
+        let has = |set: &[&str]| names.iter().any(|n| set.contains(&n.as_str()));
+        if has(&TEST)
+        {
+            tags.extend(out.tags.iter().cloned());
+            let unfit = unfit(env, &method, &name, false)?;
+            out.tests.push(TestMethod { name, method, skip: has(&SKIP), tags, unfit });
+            continue;
+        }
+        let hook = has(&BEFORE) || has(&AFTER) || has(&BEFORE_ALL) || has(&AFTER_ALL);
+        if hook
+            && let Some(f) = unfit(env, &method, &name, has(&BEFORE_ALL) || has(&AFTER_ALL))?
+        {
+            out.unfit.push(f);
+        }
+        else if has(&BEFORE)
+        {
+            out.before.push(method);
+        }
+        else if has(&AFTER)
+        {
+            out.after.push(method);
+        }
+        else if has(&BEFORE_ALL)
+        {
+            out.before_all.push(method);
+        }
+        else if has(&AFTER_ALL)
+        {
+            out.after_all.push(method);
+        }
+    }
+    out.tests.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(out)
+}
+
+/// `throwable.printStackTrace()` into a string.
+fn  stack_trace(env: &mut JNIEnv, throwable: &JObject) -> Result<String, jni::errors::Error>
+{
+    let writer = env.new_object("java/io/StringWriter", "()V", &[])?;
+    let printer = env.new_object("java/io/PrintWriter", "(Ljava/io/Writer;)V", &[JValue::Object(&writer)])?;
+    env.call_method(throwable, "printStackTrace", "(Ljava/io/PrintWriter;)V", &[JValue::Object(&printer)])?;
+    env.call_method(&printer, "flush", "()V", &[])?;
+    string_of(env, &writer, "toString")
+}
+
+/// Clears the pending exception, unwrapping reflection's
+/// `InvocationTargetException`, and returns what the test actually threw.
+fn  thrown(env: &mut JNIEnv) -> Failure
+{
+    let Ok(exception) = env.exception_occurred() else { return "unknown exception".to_string().into() };
+    let _ = env.exception_clear();
+    let mut throwable = JObject::from(exception);
+    if env.is_instance_of(&throwable, "java/lang/reflect/InvocationTargetException").unwrap_or(false)
+        && let Ok(cause) = env.call_method(&throwable, "getCause", "()Ljava/lang/Throwable;", &[]).and_then(|x| x.l())
+        && !cause.is_null()
+    {
+        throwable = cause;
+    }
+    let message = string_of(env, &throwable, "toString").unwrap_or_else(|_| "unknown exception".to_string());
+    let _ = env.exception_clear();
+    let trace = stack_trace(env, &throwable).ok();
+    let _ = env.exception_clear();
+    let assertion = env.is_instance_of(&throwable, "java/lang/AssertionError").unwrap_or(false);
+    let _ = env.exception_clear();
+    Failure { message, trace, assertion }
+}
+
+#[inline(always)]
+fn  failure(env: &mut JNIEnv, e: jni::errors::Error) -> Failure
+{
+    match e
+    {
+        jni::errors::Error::JavaException => thrown(env),
+        e => e.to_string().into(),
+    }
+}
+
+#[inline(always)]
+fn  method_id(env: &JNIEnv, method: &JObject) -> Result<jmethodID, String>
+{
+    let raw = env.get_raw();
+    // SAFETY: `raw` is this thread's live JNIEnv and `method` a local reference
+    // to a java.lang.reflect.Method.
+    let id = unsafe
+    {
+        match (**raw).FromReflectedMethod
+        {
+            Some(f) => f(raw, method.as_raw()),
+            None => std::ptr::null_mut(),
+        }
+    };
+    if id.is_null()
+    {
+        return Err("FromReflectedMethod failed".to_string());
+    }
+    Ok(id)
+}
+
+/// Calls a no-arg method straight through JNI rather than `Method.invoke`,
+/// which needs a Java caller for its access checks. A null `target` means a
+/// static method of `class`.
+fn  invoke(env: &mut JNIEnv, class: &JClass, method: &JObject, target: &JObject) -> Result<(), Failure>
+{
+    let id = method_id(env, method)?;
+    let void = ReturnType::Primitive(Primitive::Void);
+    // SAFETY: `id` was just resolved from `method`, which `discover` checked
+    // takes no arguments, returns void, and is static exactly when `target`
+    // is null.
+    let result = unsafe
+    {
+        if target.is_null()
+        {
+            env.call_static_method_unchecked(class, JStaticMethodID::from_raw(id), void, &[])
+        }
+        else
+        {
+            env.call_method_unchecked(target, JMethodID::from_raw(id), void, &[])
+        }
+    };
+    result.map(|_| ()).map_err(|e| failure(env, e))
+}
+
+fn  new_instance<'a>(env: &mut JNIEnv<'a>, class: &JClass) -> Result<JObject<'a>, Failure>
+{
+    let result = env.get_method_id(class, "<init>", "()V")
+        // SAFETY: the constructor id matches `class` and takes no arguments.
+        .and_then(|ctor| unsafe { env.new_object_unchecked(class, ctor, &[]) });
+    result.map_err(|e| failure(env, e))
+}
+
+/// Calls `main` with the runtime arguments from lvjb.toml, like `lvjb run`.
+fn  run_main(env: &mut JNIEnv, class: &JClass, config: &Config) -> Result<(), jni::errors::Error>
+{
+    let args = config.args.runtime.clone().unwrap_or_default();
+    let array = env.new_object_array(args.len() as i32, "java/lang/String", JObject::null())?;
+    for (i, arg) in args.iter().enumerate()
+    {
+        let s = env.new_string(arg)?;
+        env.set_object_array_element(&array, i as i32, s)?;
+    }
+    env.call_static_method(class, "main", "([Ljava/lang/String;)V", &[JValue::Object(&array)])?;
+    Ok(())
+}
+
+/// Runs one `@Test` method on a fresh instance, wrapped in the before/after
+/// hooks. After-hooks run even when the test or a before-hook failed.
+fn  run_method(env: &mut JNIEnv, class: &JClass, methods: &Methods, test: &JObject) -> Outcome
+{
+    let instance = match new_instance(env, class)
+    {
+        Ok(x) => x,
+        Err(e) => return Outcome::Failed(e),
+    };
+    let mut result = methods.before.iter()
+        .try_for_each(|m| invoke(env, class, m, &instance))
+        .and_then(|_| invoke(env, class, test, &instance));
+    for m in &methods.after
+    {
+        if let Err(e) = invoke(env, class, m, &instance)
+            && result.is_ok()
+        {
+            result = Err(e);
+        }
+    }
+    match result
+    {
+        Ok(_) => Outcome::Passed,
+        Err(e) => Outcome::Failed(e),
+    }
+}
+
+/// Installs `lvjb.ExitGuard` in the shared VM, or removes it. Returns false
+/// when the JDK no longer supports security managers.
+pub fn  exit_guard(jvm: &JavaVM, config: &Config, install: bool) -> Result<bool, Box<dyn std::error::Error>>
+{
+    let mut env = jvm.attach_current_thread()?;
+    let result = env.with_local_frame(16, |env| -> Result<bool, jni::errors::Error>
+    {
+        let loader = class_loader(env, config)?;
+        let class = load_class(env, &loader, "lvjb.ExitGuard")?;
+        // JDK 17 prints a deprecation warning for every class that sets the
+        // security manager, straight to the VM's stderr. Keep it out of test runs.
+        let null = fs::OpenOptions::new().write(true).open("/dev/null").ok();
+        // SAFETY: fd 2 is swapped for /dev/null and restored right after the call.
+        let saved = null.as_ref().map(|n| unsafe
+        {
+            let saved = libc::dup(2);
+            libc::dup2(n.as_raw_fd(), 2);
+            saved
+        });
+        let result = match install
+        {
+            true => env.call_static_method(&class, "install", "()Z", &[]).and_then(|x| x.z()),
+            false => env.call_static_method(&class, "uninstall", "()V", &[]).map(|_| true),
+        };
+        if let Some(fd) = saved
+        {
+            unsafe
+            {
+                libc::dup2(fd, 2);
+                libc::close(fd);
+            }
+        }
+        result
+    });
+    result.map_err(|e| failure(&mut env, e).message.into())
+}
+
+/// Names of the `@Test` methods of `class_name` that `filter` selects and
+/// whether each is skipped, and whether the class is run through its `main`.
+fn  test_names(class_name: &str, config: &Config, jvm: &JavaVM, filter: &TestFilter) -> Result<(Vec<(String, bool)>, bool), Failure>
+{
+    let mut env = jvm.attach_current_thread().map_err(|e| Failure::from(e.to_string()))?;
+    let result = env.with_local_frame(64, |env| -> Result<(Vec<(String, bool)>, bool), jni::errors::Error>
+    {
+        let loader = class_loader(env, config)?;
+        let class = load_class(env, &loader, class_name)?;
+        let methods = discover(env, &class)?;
+        let main = methods.tests.is_empty() && methods.has_main && filter.matches(class_name, None, &methods.tags);
+        Ok((methods.tests.into_iter()
+            .filter(|t| filter.matches(class_name, Some(&t.name), &t.tags))
+            .map(|t| (t.name, t.skip))
+            .collect(), main))
+    });
+    result.map_err(|e| failure(&mut env, e))
+}
+
+static FORKED: AtomicUsize = AtomicUsize::new(0);
+
+/// Waits for `child`, killing it once `limit` has passed.
+fn  wait_child(child: &mut process::Child, limit: Option<Duration>) -> Result<Option<process::ExitStatus>, std::io::Error>
+{
+    let started = Instant::now();
+    loop
+    {
+        if let Some(status) = child.try_wait()?
+        {
+            return Ok(Some(status));
+        }
+        if limit.is_some_and(|l| started.elapsed() > l)
+        {
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+        sleep(Duration::from_millis(10));
+    }
+}
+
+/// Runs one test method (or a `main`-style class) in a child `lvjb`, which
+/// writes its results as JSON to a temporary file. The child stops its own
+/// timed-out tests and gives up on hung ones; it is killed if it is still
+/// running well after that.
+fn  run_forked(class_name: &str, method: Option<&str>, timeout: Option<Duration>) -> Vec<TestResult>
+{
+    let started = Instant::now();
+    let out = env::temp_dir().join(format!("lvjb-test-{}-{}.json", process::id(), FORKED.fetch_add(1, Ordering::Relaxed)));
+    let hits = out.with_extension("cov");
+    let status = env::current_exe()
+        .and_then(|exe|
+        {
+            let mut cmd = Command::new(exe);
+            cmd.arg(WORKER_CMD).arg(&out).arg(class_name).args(method);
+            if let Some(recorder) = worker_env()
+            {
+                cmd.env(COVERAGE_ENV, recorder);
+            }
+            cmd.spawn()
+        })
+        .and_then(|mut child| wait_child(&mut child, timeout.map(|t| t + 2 * STOP_GRACE)));
+    let results = fs::read_to_string(&out).ok()
+        .and_then(|s| serde_json::from_str::<Vec<TestResult>>(&s).ok());
+    if let Some(ids) = fs::read_to_string(&hits).ok().and_then(|s| serde_json::from_str(&s).ok())
+    {
+        add_forked_hits(ids);
+    }
+    let _ = fs::remove_file(&out);
+    let _ = fs::remove_file(&hits);
+    let message = match (results, status)
+    {
+        (Some(x), _) => return x,
+        (None, Ok(Some(status))) => format!("test process exited with {status} before reporting"),
+        (None, Ok(None)) => format!("timed out after {}s, test process killed", timeout.unwrap_or_default().as_secs()),
+        (None, Err(e)) => format!("couldn't run test process: {e}"),
+    };
+    vec![TestResult::new(class_name, method.map(String::from), Outcome::Failed(message.into()), started.elapsed())]
+}
+
+/// Runs one test method of `class_name` (or its `main`) on its own: through
+/// a fresh class loader in this VM, or in a child process in process mode.
+fn  run_one(class_name: &str, config: &Config, jvm: &'static JavaVM, hung: &HungSlot, method: Option<&str>) -> Vec<TestResult>
+{
+    match config.test_isolation.as_str()
+    {
+        ISOLATION_PROCESS => run_forked(class_name, method, test_timeout(config, class_name)),
+        _ => run_class(class_name, config, jvm, None, method, hung, &TestFilter::default()),
+    }
+}
+
+/// Runs each test method of `class_name` on its own, see `run_one`.
+fn  run_isolated(class_name: &str, config: &Config, jvm: &'static JavaVM, halt: Option<&AtomicBool>, hung: &HungSlot, filter: &TestFilter) -> Vec<TestResult>
+{
+    let (names, main) = match test_names(class_name, config, jvm, filter)
+    {
+        Ok(x) => x,
+        Err(f) => return vec![TestResult::new(class_name, None, Outcome::Failed(f), Duration::ZERO)],
+    };
+    if names.is_empty()
+    {
+        return if main { run_one(class_name, config, jvm, hung, None) } else { Vec::new() };
+    }
+    let mut results = Vec::with_capacity(names.len());
+    for (name, skip) in &names
+    {
+        if halt.is_some_and(|h| h.load(Ordering::Relaxed))
+        {
+            break;
+        }
+        if *skip
+        {
+            results.push(TestResult::new(class_name, Some(name.clone()), Outcome::Skipped, Duration::ZERO));
+            continue;
+        }
+        let one = run_one(class_name, config, jvm, hung, Some(name));
+        if let Some(h) = halt
+            && one.iter().any(|r| matches!(r.outcome, Outcome::Failed(_)) && !quarantined(config, &r.class, r.method.as_deref()))
+        {
+            h.store(true, Ordering::Relaxed);
+        }
+        results.extend(one);
+    }
+    results
+}
+
+/// Entry point of `lvjb test-worker <out> <class> [method]`: runs one test in
+/// this process's own VM and writes the results to `out`, and the coverage
+/// probes it hit next to it.
+pub fn  run_worker(out: &Path, class_name: &str, method: Option<&str>, config: &mut Config) -> Result<(), Box<dyn std::error::Error>>
+{
+    let recorder = Recorder::from_env();
+    if let Some(r) = &recorder
+    {
+        r.apply(config);
+    }
+    let config = &*config;
+    let jvm = shared_jvm(config)?;
+    exit_guard(jvm, config, true)?;
+    start_capture(jvm, config)?;
+    if let Some(r) = &recorder
+    {
+        r.start(jvm)?;
+    }
+    // A test that can't be stopped is reported from here, then the process
+    // exits instead of waiting for the parent to kill it.
+    let hung = HungSlot::default();
+    let (slot, path) = (Arc::clone(&hung), out.to_path_buf());
+    thread::spawn(move || loop
+    {
+        sleep(Duration::from_millis(50));
+        if let Some(r) = slot.lock().ok().and_then(|x| x.clone())
+        {
+            let _ = fs::write(&path, serde_json::to_string(&[r]).unwrap_or_default());
+            process::exit(1);
+        }
+    });
+    let results = run_class(class_name, config, jvm, None, method, &hung, &TestFilter::default());
+    flush_java_streams();
+    if let Some(r) = &recorder
+    {
+        fs::write(out.with_extension("cov"), serde_json::to_string(&r.finish(jvm)?)?)?;
+    }
+    fs::write(out, serde_json::to_string(&results)?)?;
+    Ok(())
+}
+
+/// Runs every test in `class_name`: its `@Test` methods if it has any, its
+/// `main` otherwise. Classes with neither are not tests and yield nothing.
+/// `test_isolation` decides where each test runs. With `halt`, tests stop
+/// being started once it is set, and a failure sets it. A test that hangs
+/// past its timeout ends up in `hung`, and the thread should be given up on.
+/// Tests `filter` doesn't select are left out.
+pub fn  run_test_class(class_name: &str, config: &Config, jvm: &'static JavaVM, halt: Option<&AtomicBool>, hung: &HungSlot, filter: &TestFilter, retries: u32) -> Vec<TestResult>
+{
+    // A failure that passes on a retry must not stop the run.
+    let run = |halt| match config.test_isolation.as_str()
+    {
+        ISOLATION_CLASSLOADER | ISOLATION_PROCESS => run_isolated(class_name, config, jvm, halt, hung, filter),
+        _ => run_class(class_name, config, jvm, halt, None, hung, filter),
+    };
+    let mut results = run(if retries > 0 { None } else { halt });
+    for _ in 0..retries
+    {
+        if hung.lock().is_ok_and(|x| x.is_some())
+        {
+            break;
+        }
+        // A class failing as a whole, like a `main`-style test, is rerun as a whole.
+        if let [only] = results.as_mut_slice()
+            && only.method.is_none()
+            && let Outcome::Failed(f) = &only.outcome
+        {
+            let mut retried = std::mem::take(&mut only.retried);
+            retried.push(f.clone());
+            results = run(None);
+            if let [again] = results.as_mut_slice()
+                && again.method.is_none()
+            {
+                again.retried = retried;
+            }
+            continue;
+        }
+        for r in results.iter_mut()
+        {
+            let (Some(method), Outcome::Failed(f)) = (&r.method, &r.outcome) else { continue };
+            if hung.lock().is_ok_and(|x| x.is_some())
+            {
+                break;
+            }
+            let Some(mut again) = run_one(class_name, config, jvm, hung, Some(method)).into_iter().find(|x| x.method == r.method) else { continue };
+            again.retried = std::mem::take(&mut r.retried);
+            again.retried.push(f.clone());
+            *r = again;
+        }
+    }
+    for r in results.iter_mut()
+    {
+        r.quarantined = quarantined(config, &r.class, r.method.as_deref());
+    }
+    if let Some(flag) = halt
+        && results.iter().any(TestResult::fails_build)
+    {
+        flag.store(true, Ordering::Relaxed);
+    }
+    results
+}
+
+/// Whether `class.method`, or all of `class`, is on the `quarantine` list.
+#[inline(always)]
+pub fn  quarantined(config: &Config, class: &str, method: Option<&str>) -> bool
+{
+    config.quarantine.iter().any(|q| q == class || method.is_some_and(|m| q.strip_prefix(class).and_then(|q| q.strip_prefix('.')) == Some(m)))
+}
+
+/// Runs `f` under a `Watchdog` if there is a `timeout`. A test that ran out
+/// of time fails with the thread dump as its trace.
+fn  timed(env: &mut JNIEnv, jvm: &'static JavaVM, timeout: Option<Duration>, hung: &HungSlot, pending: TestResult, f: impl FnOnce(&mut JNIEnv) -> Outcome) -> Outcome
+{
+    let Some(limit) = timeout else { return f(env) };
+    let watchdog = match Watchdog::arm(env, jvm, limit, hung, pending)
+    {
+        Ok(x) => x,
+        Err(e) => return Outcome::Failed(failure(env, e)),
+    };
+    let outcome = f(env);
+    match watchdog.disarm()
+    {
+        Some(dump) => Outcome::Failed(Failure { message: format!("timed out after {}s", limit.as_secs()), trace: Some(dump), assertion: false }),
+        None => outcome,
+    }
+}
+
+/// Runs the tests of `class_name` that `filter` selects (only `only`, if
+/// given) with one fresh class loader.
+fn  run_class(class_name: &str, config: &Config, jvm: &'static JavaVM, halt: Option<&AtomicBool>, only: Option<&str>, hung: &HungSlot, filter: &TestFilter) -> Vec<TestResult>
+{
+    let started = Instant::now();
+    let timeout = test_timeout(config, class_name);
+    let pending = |method: Option<&String>| TestResult::new(class_name, method.cloned(), Outcome::Skipped, timeout.unwrap_or_default());
+    let failed = |f: Failure| vec![TestResult::new(class_name, None, Outcome::Failed(f), started.elapsed())];
+    let mut env = match jvm.attach_current_thread()
+    {
+        Ok(x) => x,
+        Err(e) => return failed(e.to_string().into()),
+    };
+    let result = env.with_local_frame(256, |env| -> Result<Vec<TestResult>, jni::errors::Error>
+    {
+        let loader = class_loader(env, config)?;
+        let class = load_class(env, &loader, class_name)?;
+        let mut methods = discover(env, &class)?;
+        if methods.tests.is_empty()
+        {
+            if !methods.has_main || !filter.matches(class_name, None, &methods.tags)
+            {
+                return Ok(Vec::new());
+            }
+            begin_capture(env);
+            let outcome = timed(env, jvm, timeout, hung, pending(None), |env| match run_main(env, &class, config)
+            {
+                Ok(_) => Outcome::Passed,
+                Err(e) => Outcome::Failed(failure(env, e)),
+            });
+            let output = end_capture(env);
+            return Ok(vec![TestResult { output, ..TestResult::new(class_name, None, outcome, started.elapsed()) }]);
+        }
+
+        methods.tests.retain(|t| only.is_none_or(|o| o == t.name) && filter.matches(class_name, Some(&t.name), &t.tags));
+        if methods.tests.is_empty()
+        {
+            return Ok(Vec::new());
+        }
+        let mut results = Vec::with_capacity(methods.tests.len());
+        let null = JObject::null();
+        // None of the hooks run if one of them can't be called.
+        let setup = match methods.unfit.first()
+        {
+            Some(f) => Err(f.context("invalid hook")),
+            None => methods.before_all.iter()
+                .try_for_each(|m| invoke(env, &class, m, &null))
+                .map_err(|e| e.context("before-all hook failed")),
+        };
+        for TestMethod { name, method: test, skip, unfit, .. } in &methods.tests
+        {
+            if halt.is_some_and(|h| h.load(Ordering::Relaxed))
+            {
+                break;
+            }
+            let t = Instant::now();
+            let (outcome, output) = match (&setup, skip, unfit)
+            {
+                (_, true, _) => (Outcome::Skipped, String::new()),
+                (_, _, Some(f)) => (Outcome::Failed(f.clone()), String::new()),
+                (Err(e), _, _) => (Outcome::Failed(e.clone()), String::new()),
+                (Ok(_), false, None) => env.with_local_frame(64, |env| -> Result<(Outcome, String), jni::errors::Error>
+                {
+                    begin_capture(env);
+                    let outcome = timed(env, jvm, timeout, hung, pending(Some(name)), |env| run_method(env, &class, &methods, test));
+                    Ok((outcome, end_capture(env)))
+                })?,
+            };
+            if let (Some(h), Outcome::Failed(_)) = (halt, &outcome)
+                && !quarantined(config, class_name, Some(name))
+            {
+                h.store(true, Ordering::Relaxed);
+            }
+            results.push(TestResult { output, ..TestResult::new(class_name, Some(name.clone()), outcome, t.elapsed()) });
+        }
+        for m in methods.after_all.iter().filter(|_| methods.unfit.is_empty())
+        {
+            if let Err(e) = invoke(env, &class, m, &null)
+            {
+                results.push(TestResult::new(class_name, None, Outcome::Failed(e.context("after-all hook failed")), Duration::ZERO));
+            }
+        }
+        Ok(results)
+    });
+    match result
+    {
+        Ok(x) => x,
+        Err(e) => failed(failure(&mut env, e)),
+    }
+}
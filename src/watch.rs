@@ -0,0 +1,112 @@
+use crate::cmds::operand;
+use crate::config::*;
+use crate::fs::*;
+use crate::spawn::{GREEN, ORANGE, RED, RESET};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, SystemTime};
+
+const POLL: Duration = Duration::from_millis(200);
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+type Snapshot = BTreeMap<PathBuf, (Option<SystemTime>, u64)>;
+
+static WATCHING: AtomicBool = AtomicBool::new(false);
+
+/// True while the current process is watching, so `run` must survive the
+/// program's `System.exit`.
+#[inline(always)]
+pub fn  watching() -> bool
+{
+    WATCHING.load(Ordering::Relaxed)
+}
+
+/// mtime and size of every watched file: sources, tests and lvjb.toml.
+fn  snapshot(config: &Config) -> Snapshot
+{
+    let mut files = fetch_files_under(&PathBuf::from(&config.paths.src), &config.src_ext);
+    files.extend(fetch_files_under(&PathBuf::from(&config.paths.test), &config.src_ext));
+    files.push(PathBuf::from(CONF_FILE));
+    files.into_iter()
+        .filter_map(|p|
+        {
+            let meta = fs::metadata(&p).ok()?;
+            Some((p, (meta.modified().ok(), meta.len())))
+        })
+        .collect()
+}
+
+/// Blocks until the watched tree changes and then stays quiet for `DEBOUNCE`,
+/// so a burst of editor saves triggers a single rebuild.
+fn  wait_for_change(config: &Config, last: &Snapshot) -> Snapshot
+{
+    loop
+    {
+        sleep(POLL);
+        let mut current = snapshot(config);
+        if current == *last
+        {
+            continue;
+        }
+        loop
+        {
+            sleep(DEBOUNCE);
+            let next = snapshot(config);
+            if next == current
+            {
+                return current;
+            }
+            current = next;
+        }
+    }
+}
+
+/// Reruns `cmd` through `handler` every time a source, test or lvjb.toml
+/// changes. `build` without a package, `run` and `test` build all sources.
+/// Failures are reported and watching carries on.
+pub fn  watch(cmd: &str, extra: &[String], handler: fn(Vec<String>) -> Result<(), i32>) -> Result<(), Box<dyn std::error::Error>>
+{
+    let steps: Vec<Vec<String>> = match cmd
+    {
+        "build" =>
+        {
+            let mut build = [vec!["lvjb".to_string(), "build".to_string()], extra.to_vec()].concat();
+            if operand(&build).is_none()
+            {
+                build.insert(2, "all".to_string());
+            }
+            vec![build.split_off(1)]
+        }
+        "run" | "test" => vec![
+            vec!["build".to_string(), "all".to_string()],
+            [vec![cmd.to_string()], extra.to_vec()].concat(),
+        ],
+        _ => return Err(format!("{RED}[WATCH]{RESET} Can only watch build, test or run, got '{cmd}'").into()),
+    };
+    let mut config = Config::load()?;
+    let mut last = snapshot(&config);
+    WATCHING.store(true, Ordering::Relaxed);
+    loop
+    {
+        for step in &steps
+        {
+            let mut args = vec!["lvjb".to_string()];
+            args.extend(step.iter().cloned());
+            if handler(args).is_err()
+            {
+                eprintln!("{RED}[WATCH]{RESET} '{}' failed", step.join(" "));
+                break;
+            }
+        }
+        eprintln!("{ORANGE}[WATCH]{RESET} Waiting for changes in {}, {} and {CONF_FILE}...", config.paths.src, config.paths.test);
+        last = wait_for_change(&config, &last);
+        if let Ok(x) = Config::load()
+        {
+            config = x;
+        }
+        eprintln!("{GREEN}[WATCH]{RESET} Change detected, rerunning '{cmd}'");
+    }
+}
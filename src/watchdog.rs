@@ -0,0 +1,128 @@
+use crate::config::*;
+use crate::testrunner::*;
+use jni::objects::*;
+use jni::{JNIEnv, JavaVM};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How long a stopped test thread gets to unwind before it is given up on.
+pub const STOP_GRACE: Duration = Duration::from_secs(5);
+
+/// Where a watchdog leaves the result of a test that hung and couldn't be
+/// stopped, so the runner can stop waiting for its thread.
+pub type HungSlot = Arc<Mutex<Option<TestResult>>>;
+
+/// Timeout of the tests in `class`: its entry in `test_timeouts`, else
+/// `test_timeout`. Zero means no limit.
+#[inline(always)]
+pub fn  test_timeout(config: &Config, class: &str) -> Option<Duration>
+{
+    let secs = config.test_timeouts.get(class).copied().unwrap_or(config.test_timeout);
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+#[inline(always)]
+fn  string_of(env: &mut JNIEnv, obj: &JObject, method: &str) -> Result<String, jni::errors::Error>
+{
+    let s = env.call_method(obj, method, "()Ljava/lang/String;", &[])?.l()?;
+    let out = env.get_string(&JString::from(s))?.into();
+    Ok(out)
+}
+
+/// Stack traces of every live thread, `focus` first, in jstack's layout.
+pub fn  thread_dump(env: &mut JNIEnv, focus: &JObject) -> Result<String, jni::errors::Error>
+{
+    let traces = env.call_static_method("java/lang/Thread", "getAllStackTraces", "()Ljava/util/Map;", &[])?.l()?;
+    let entries = env.call_method(&traces, "entrySet", "()Ljava/util/Set;", &[])?.l()?;
+    let it = env.call_method(&entries, "iterator", "()Ljava/util/Iterator;", &[])?.l()?;
+    let mut threads: Vec<(bool, String)> = Vec::new();
+    while env.call_method(&it, "hasNext", "()Z", &[])?.z()?
+    {
+        let thread = env.with_local_frame(16, |env| -> Result<(bool, String), jni::errors::Error>
+        {
+            let entry = env.call_method(&it, "next", "()Ljava/lang/Object;", &[])?.l()?;
+            let thread = env.call_method(&entry, "getKey", "()Ljava/lang/Object;", &[])?.l()?;
+            let frames = JObjectArray::from(env.call_method(&entry, "getValue", "()Ljava/lang/Object;", &[])?.l()?);
+            let name = string_of(env, &thread, "getName")?;
+            let state = env.call_method(&thread, "getState", "()Ljava/lang/Thread$State;", &[])?.l()?;
+            let mut text = format!("\"{name}\" {}\n", string_of(env, &state, "toString")?);
+            for i in 0..env.get_array_length(&frames)?
+            {
+                let frame = env.get_object_array_element(&frames, i)?;
+                text.push_str(&format!("\tat {}\n", string_of(env, &frame, "toString")?));
+                env.delete_local_ref(frame)?;
+            }
+            Ok((env.is_same_object(&thread, focus)?, text))
+        })?;
+        threads.push(thread);
+    }
+    threads.sort_by_key(|(is_focus, _)| !is_focus);
+    Ok(threads.into_iter().map(|(_, t)| t).collect::<Vec<_>>().join("\n"))
+}
+
+/// `Thread.stop()`, which unwinds the test with a `ThreadDeath`. JDKs that
+/// dropped it get an `interrupt()` instead.
+fn  stop_thread(env: &mut JNIEnv, thread: &JObject)
+{
+    if env.call_method(thread, "stop", "()V", &[]).is_err()
+    {
+        let _ = env.exception_clear();
+        let _ = env.call_method(thread, "interrupt", "()V", &[]);
+        let _ = env.exception_clear();
+    }
+}
+
+/// Watches one test running on the current thread. Past `timeout` it takes a
+/// thread dump and stops the thread. If the thread doesn't come back within
+/// `STOP_GRACE`, the test is reported as hung through the slot.
+pub struct Watchdog
+{
+    done:   mpsc::Sender<()>,
+    fired:  Arc<Mutex<Option<String>>>,
+}
+
+impl Watchdog
+{
+    pub fn  arm(env: &mut JNIEnv, jvm: &'static JavaVM, timeout: Duration, hung: &HungSlot, pending: TestResult) -> Result<Self, jni::errors::Error>
+    {
+        let current = env.call_static_method("java/lang/Thread", "currentThread", "()Ljava/lang/Thread;", &[])?.l()?;
+        let thread = env.new_global_ref(current)?;
+        let (done, wait) = mpsc::channel::<()>();
+        let fired = Arc::new(Mutex::new(None));
+        let (slot, hung) = (Arc::clone(&fired), Arc::clone(hung));
+        thread::spawn(move ||
+        {
+            if wait.recv_timeout(timeout) != Err(RecvTimeoutError::Timeout)
+            {
+                return;
+            }
+            let Ok(mut env) = jvm.attach_current_thread() else { return };
+            let dump = thread_dump(&mut env, thread.as_obj()).unwrap_or_else(|e| format!("thread dump failed: {e}"));
+            let _ = env.exception_clear();
+            if let Ok(mut x) = slot.lock()
+            {
+                *x = Some(dump.clone());
+            }
+            stop_thread(&mut env, thread.as_obj());
+            if wait.recv_timeout(STOP_GRACE) != Err(RecvTimeoutError::Timeout)
+            {
+                return;
+            }
+            let message = format!("timed out after {}s and could not be stopped", timeout.as_secs());
+            if let Ok(mut x) = hung.lock()
+            {
+                *x = Some(TestResult { outcome: Outcome::Failed(Failure { message, trace: Some(dump), assertion: false }), ..pending });
+            }
+        });
+        Ok(Watchdog { done, fired })
+    }
+
+    /// Stops watching. Returns the thread dump if the test timed out.
+    pub fn  disarm(self) -> Option<String>
+    {
+        let _ = self.done.send(());
+        self.fired.lock().ok()?.take()
+    }
+}